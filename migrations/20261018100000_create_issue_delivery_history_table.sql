@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE issue_delivery_history (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    attempted_at timestamptz NOT NULL
+);
+CREATE INDEX issue_delivery_history_subscriber_email_idx ON issue_delivery_history (subscriber_email);
@@ -0,0 +1,57 @@
+-- Add migration script here
+-- Addresses differing only by case or surrounding whitespace belong to the same person.
+-- Keep one subscription per address, preferring a confirmed one, then the oldest.
+BEGIN;
+    CREATE TEMPORARY TABLE subscription_duplicates ON COMMIT DROP AS
+    SELECT s.id AS duplicate_id, s.email AS duplicate_email, k.id AS kept_id, k.email AS kept_email
+    FROM (
+        SELECT
+            id,
+            email,
+            first_value(id) OVER (
+                PARTITION BY lower(trim(email))
+                ORDER BY (status = 'confirmed') DESC, subscribed_at, id
+            ) AS kept_id
+        FROM subscriptions
+    ) s
+    JOIN subscriptions k ON k.id = s.kept_id
+    WHERE s.id <> s.kept_id;
+
+    UPDATE subscription_tokens t
+    SET subscriber_id = d.kept_id
+    FROM subscription_duplicates d
+    WHERE t.subscriber_id = d.duplicate_id;
+
+    -- The kept subscription already has its own deliveries queued, if it is confirmed
+    DELETE FROM issue_delivery_queue q
+    USING subscription_duplicates d
+    WHERE q.subscriber_email = d.duplicate_email;
+
+    UPDATE issue_delivery_history h
+    SET subscriber_email = d.kept_email
+    FROM subscription_duplicates d
+    WHERE h.subscriber_email = d.duplicate_email;
+
+    DELETE FROM subscriptions s
+    USING subscription_duplicates d
+    WHERE s.id = d.duplicate_id;
+
+    -- Trim and lowercase the domain, as `SubscriberEmail::parse` now does.
+    -- Internationalised domains are converted to punycode only for new subscriptions.
+    CREATE FUNCTION pg_temp.normalise_email(email TEXT) RETURNS TEXT AS $$
+        SELECT CASE
+            WHEN trim(email) LIKE '%@%'
+            THEN substring(trim(email) from '^(.*)@') || '@' || lower(substring(trim(email) from '@([^@]*)$'))
+            ELSE trim(email)
+        END
+    $$ LANGUAGE SQL IMMUTABLE;
+
+    UPDATE subscriptions SET email = pg_temp.normalise_email(email);
+    UPDATE issue_delivery_queue SET subscriber_email = pg_temp.normalise_email(subscriber_email);
+    UPDATE issue_delivery_history SET subscriber_email = pg_temp.normalise_email(subscriber_email);
+
+    CREATE UNIQUE INDEX subscriptions_email_normalised_key ON subscriptions (lower(email));
+
+    DROP INDEX issue_delivery_history_subscriber_email_idx;
+    CREATE INDEX issue_delivery_history_subscriber_email_idx ON issue_delivery_history (lower(subscriber_email));
+COMMIT;
@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE user_totp (
+    user_id uuid PRIMARY KEY REFERENCES users (user_id) ON DELETE CASCADE,
+    -- Base32 encoded shared secret
+    secret TEXT NOT NULL,
+    -- NULL until the user has proven their authenticator works
+    enabled_at timestamptz,
+    -- The last time step a code was accepted for, codes cannot be replayed
+    last_used_step BIGINT
+);
+
+CREATE TABLE user_recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- SHA-256 of the code, codes are random enough not to need a slow hash
+    code_hash TEXT NOT NULL,
+    used_at timestamptz,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN email TEXT;
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+CREATE TABLE password_reset_tokens (
+    -- SHA-256 of the token, the token itself is only ever sent by email
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
+CREATE INDEX password_reset_tokens_user_id ON password_reset_tokens (user_id);
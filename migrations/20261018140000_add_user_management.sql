@@ -0,0 +1,18 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE users ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+
+CREATE TABLE user_invitations (
+    -- SHA-256 of the token, the token itself is only ever sent by email
+    token_hash TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    invited_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    accepted_at timestamptz,
+    accepted_user_id uuid REFERENCES users (user_id) ON DELETE SET NULL
+);
+
+-- Issues published before this migration have no known author
+ALTER TABLE newsletter_issues ADD COLUMN published_by uuid REFERENCES users (user_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Existing users could do everything, they keep doing so
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+
+ALTER TABLE user_invitations ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- The seed admin was shipped with a well-known password hash. Drop it wherever that
+-- password has not been changed: the first admin is now created with
+-- `zero2prod create-admin`.
+CREATE TEMPORARY TABLE seed_users AS
+SELECT user_id FROM users
+WHERE username = 'admin'
+    AND password_hash = '$argon2id$v=19$m=19456,t=2,p=1$w9RJhR+rgvtN8KrWVxxtqg$9TzJS1ZoiO4Wo+xqLu3fWsM74fFP9h2RQSV5SfnmQMg';
+
+UPDATE newsletter_issues SET published_by = NULL
+WHERE published_by IN (SELECT user_id FROM seed_users);
+DELETE FROM idempotency WHERE user_id IN (SELECT user_id FROM seed_users);
+DELETE FROM users WHERE user_id IN (SELECT user_id FROM seed_users);
+
+DROP TABLE seed_users;
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE api_tokens (
+    token_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token, the token itself is only shown once when it is created
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
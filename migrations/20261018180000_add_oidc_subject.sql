@@ -0,0 +1,3 @@
+-- Add migration script here
+-- The `sub` claim of the identity provider account linked to the user
+ALTER TABLE users ADD COLUMN oidc_subject TEXT UNIQUE;
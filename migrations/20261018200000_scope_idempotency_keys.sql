@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Keys used with an API token are scoped to that token rather than to its owner
+ALTER TABLE idempotency
+    ADD COLUMN api_token_id uuid REFERENCES api_tokens (token_id) ON DELETE CASCADE;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+CREATE UNIQUE INDEX idempotency_user_key_idx
+    ON idempotency (user_id, idempotency_key) WHERE api_token_id IS NULL;
+CREATE UNIQUE INDEX idempotency_api_token_key_idx
+    ON idempotency (api_token_id, idempotency_key) WHERE api_token_id IS NOT NULL;
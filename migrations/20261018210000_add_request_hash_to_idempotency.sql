@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Digest of the request a key was first used with, NULL for keys saved before it was recorded
+ALTER TABLE idempotency ADD COLUMN request_hash TEXT;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Expired keys are looked up by age when they are cleaned up
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Keys are claimed in their own transaction, so requests in progress show up as such
+-- instead of holding a lock on the key
+ALTER TABLE idempotency
+    ADD COLUMN state TEXT NOT NULL DEFAULT 'completed'
+    CHECK (state IN ('in_progress', 'completed'));
+ALTER TABLE idempotency ALTER COLUMN state DROP DEFAULT;
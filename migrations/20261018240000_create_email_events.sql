@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Delivery, bounce, complaint and unsubscribe events reported by the email provider's webhook
+CREATE TABLE email_events (
+    event_id BIGSERIAL PRIMARY KEY,
+    -- Webhooks are retried, events already stored are recognised by the provider's id
+    provider_event_id TEXT NOT NULL UNIQUE,
+    event_type TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX email_events_recipient_idx ON email_events (lower(recipient));
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Whether the user has shown they receive mail at `email`, e.g. by accepting an invitation
+-- sent there. Addresses typed in by the users themselves are not trusted for single sign-on.
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,229 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres};
+use uuid::Uuid;
+
+/// What an audit event records someone doing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    PasswordReset,
+    EmailChanged,
+    NewsletterPublished,
+    SessionRevoked,
+    AllSessionsRevoked,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    UserInvited,
+    UserRoleChanged,
+    UserDeactivated,
+    UserReactivated,
+    PasswordResetForced,
+    SubscriberConfirmed,
+    SubscriberUnsubscribed,
+    SubscriberDeleted,
+    SubscriberDataErased,
+    ConfirmationResent,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 23] = [
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::PasswordChanged,
+        AuditAction::PasswordReset,
+        AuditAction::EmailChanged,
+        AuditAction::NewsletterPublished,
+        AuditAction::SessionRevoked,
+        AuditAction::AllSessionsRevoked,
+        AuditAction::ApiTokenCreated,
+        AuditAction::ApiTokenRevoked,
+        AuditAction::TwoFactorEnabled,
+        AuditAction::TwoFactorDisabled,
+        AuditAction::UserInvited,
+        AuditAction::UserRoleChanged,
+        AuditAction::UserDeactivated,
+        AuditAction::UserReactivated,
+        AuditAction::PasswordResetForced,
+        AuditAction::SubscriberConfirmed,
+        AuditAction::SubscriberUnsubscribed,
+        AuditAction::SubscriberDeleted,
+        AuditAction::SubscriberDataErased,
+        AuditAction::ConfirmationResent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::EmailChanged => "email_changed",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::SessionRevoked => "session_revoked",
+            AuditAction::AllSessionsRevoked => "all_sessions_revoked",
+            AuditAction::ApiTokenCreated => "api_token_created",
+            AuditAction::ApiTokenRevoked => "api_token_revoked",
+            AuditAction::TwoFactorEnabled => "two_factor_enabled",
+            AuditAction::TwoFactorDisabled => "two_factor_disabled",
+            AuditAction::UserInvited => "user_invited",
+            AuditAction::UserRoleChanged => "user_role_changed",
+            AuditAction::UserDeactivated => "user_deactivated",
+            AuditAction::UserReactivated => "user_reactivated",
+            AuditAction::PasswordResetForced => "password_reset_forced",
+            AuditAction::SubscriberConfirmed => "subscriber_confirmed",
+            AuditAction::SubscriberUnsubscribed => "subscriber_unsubscribed",
+            AuditAction::SubscriberDeleted => "subscriber_deleted",
+            AuditAction::SubscriberDataErased => "subscriber_data_erased",
+            AuditAction::ConfirmationResent => "confirmation_resent",
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditAction {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid audit action", s))
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something someone did, about to be recorded in the audit log.
+pub struct AuditEvent<'a> {
+    /// `None` when nobody is logged in, e.g. for failed logins
+    pub actor: Option<Uuid>,
+    pub action: AuditAction,
+    /// What the action was done to, e.g. `user:<id>`
+    pub target: Option<String>,
+    pub ip: &'a str,
+}
+
+impl<'a> AuditEvent<'a> {
+    pub fn new(actor: Uuid, action: AuditAction, ip: &'a str) -> Self {
+        Self {
+            actor: Some(actor),
+            action,
+            target: None,
+            ip,
+        }
+    }
+
+    pub fn target(mut self, kind: &str, id: impl std::fmt::Display) -> Self {
+        self.target = Some(format!("{}:{}", kind, id));
+        self
+    }
+}
+
+/// Append an event to the audit log.
+///
+/// Takes any executor, so that changes made in a transaction are recorded along with it.
+#[tracing::instrument(
+    name = "Record audit event",
+    skip(executor, event),
+    fields(action = %event.action, actor = ?event.actor)
+)]
+pub async fn record_audit_event<'c, E>(executor: E, event: AuditEvent<'_>) -> Result<(), anyhow::Error>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    sqlx::query!(
+        "INSERT INTO audit_events (actor_id, action, target, ip) VALUES ($1, $2, $3, $4)",
+        event.actor,
+        event.action.as_str(),
+        event.target,
+        event.ip,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record an audit event")?;
+    Ok(())
+}
+
+pub struct AuditEventRow {
+    pub occurred_at: DateTime<Utc>,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: String,
+    /// How many events match the filter, over all pages
+    pub total: i64,
+}
+
+/// Which events to list, every field narrowing the list down.
+#[derive(Default, Debug)]
+pub struct AuditEventFilter<'a> {
+    pub action: Option<AuditAction>,
+    /// Username of the actor
+    pub actor: Option<&'a str>,
+    /// Events whose target contains this
+    pub target: Option<&'a str>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// Events matching `filter`, newest first.
+#[tracing::instrument(name = "List audit events", skip(pool))]
+pub async fn list_audit_events(
+    pool: &PgPool,
+    filter: &AuditEventFilter<'_>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEventRow>, anyhow::Error> {
+    sqlx::query_as!(
+        AuditEventRow,
+        r#"
+        SELECT
+            e.occurred_at, u.username AS "actor?", e.action, e.target, e.ip,
+            count(*) OVER () AS "total!"
+        FROM audit_events e
+        LEFT JOIN users u ON u.user_id = e.actor_id
+        WHERE ($1::text IS NULL OR e.action = $1)
+            AND ($2::text IS NULL OR u.username = $2)
+            AND ($3::text IS NULL OR strpos(e.target, $3) > 0)
+            AND ($4::timestamptz IS NULL OR e.occurred_at >= $4)
+            AND ($5::timestamptz IS NULL OR e.occurred_at < $5)
+        ORDER BY e.occurred_at DESC, e.event_id DESC
+        LIMIT $6
+        OFFSET $7
+        "#,
+        filter.action.map(|a| a.as_str()),
+        filter.actor,
+        filter.target,
+        filter.after,
+        filter.before,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch audit events")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+
+    #[test]
+    fn actions_round_trip_through_their_names() {
+        for action in AuditAction::ALL {
+            assert_eq!(AuditAction::try_from(action.as_str()), Ok(action));
+        }
+        assert!(AuditAction::try_from("unknown").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::Role;
+
+/// Lets tokens be recognised, e.g. by secret scanners.
+const API_TOKEN_PREFIX: &str = "z2p_";
+const API_TOKEN_LENGTH: usize = 40;
+
+/// What an API token may be used for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    ReadNewsletters,
+    PublishNewsletters,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::ReadNewsletters, ApiScope::PublishNewsletters];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::ReadNewsletters => "newsletters:read",
+            ApiScope::PublishNewsletters => "newsletters:publish",
+        }
+    }
+
+    /// The role the owner of a token needs for the token to use this scope.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ApiScope::ReadNewsletters => Role::Viewer,
+            ApiScope::PublishNewsletters => Role::Editor,
+        }
+    }
+}
+
+impl TryFrom<String> for ApiScope {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "newsletters:read" => Ok(ApiScope::ReadNewsletters),
+            "newsletters:publish" => Ok(ApiScope::PublishNewsletters),
+            other => Err(format!("{} is not a valid scope", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub struct ApiTokenRow {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The user on whose behalf a request authenticated with an API token is made.
+#[derive(Clone, Debug)]
+pub struct ApiCaller {
+    pub user_id: Uuid,
+    pub token_id: Uuid,
+    pub role: Role,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiCaller {
+    /// Whether the token has `scope`, and its owner still has the role it needs.
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope) && self.role.allows(scope.required_role())
+    }
+}
+
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a token for `user_id`, returning its id and the token itself.
+///
+/// Only a hash of the token is stored.
+#[tracing::instrument(name = "Create API token", skip(pool))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+) -> Result<(Uuid, String), anyhow::Error> {
+    let token: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(API_TOKEN_LENGTH)
+        .collect();
+    let token = format!("{}{}", API_TOKEN_PREFIX, token);
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    let token_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        token_id,
+        user_id,
+        name,
+        hash_api_token(&token),
+        &scopes,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an API token")?;
+    Ok((token_id, token))
+}
+
+/// The tokens of `user_id` which have not been revoked.
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiTokenRow>, anyhow::Error> {
+    sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+        SELECT token_id, name, scopes, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch API tokens")
+}
+
+/// Returns `false` if `token_id` is not a live token of `user_id`.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        token_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Look up the live token `token` belongs to, recording that it has been used.
+///
+/// Tokens of deactivated users are rejected.
+#[tracing::instrument(name = "Authenticate API token", skip_all)]
+pub async fn authenticate_api_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<ApiCaller>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens t
+        SET last_used_at = now()
+        FROM users u
+        WHERE t.token_hash = $1
+            AND t.revoked_at IS NULL
+            AND u.user_id = t.user_id
+            AND u.active
+        RETURNING t.token_id, t.user_id, t.scopes, u.role
+        "#,
+        hash_api_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an API token")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(ApiCaller {
+        user_id: row.user_id,
+        token_id: row.token_id,
+        role: Role::try_from(row.role).map_err(anyhow::Error::msg)?,
+        // Scopes we no longer know about grant nothing
+        scopes: row
+            .scopes
+            .into_iter()
+            .filter_map(|s| ApiScope::try_from(s).ok())
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiCaller, ApiScope};
+    use crate::authentication::Role;
+    use claims::assert_ok_eq;
+    use uuid::Uuid;
+
+    fn caller(role: Role, scopes: Vec<ApiScope>) -> ApiCaller {
+        ApiCaller {
+            user_id: Uuid::new_v4(),
+            token_id: Uuid::new_v4(),
+            role,
+            scopes,
+        }
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in ApiScope::ALL {
+            assert_ok_eq!(ApiScope::try_from(scope.as_str().to_string()), scope);
+        }
+    }
+
+    #[test]
+    fn tokens_only_allow_their_scopes() {
+        let caller = caller(Role::Owner, vec![ApiScope::ReadNewsletters]);
+        assert!(caller.allows(ApiScope::ReadNewsletters));
+        assert!(!caller.allows(ApiScope::PublishNewsletters));
+    }
+
+    #[test]
+    fn tokens_are_limited_by_the_role_of_their_owner() {
+        let caller = caller(Role::Viewer, ApiScope::ALL.to_vec());
+        assert!(caller.allows(ApiScope::ReadNewsletters));
+        assert!(!caller.allows(ApiScope::PublishNewsletters));
+    }
+}
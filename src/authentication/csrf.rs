@@ -0,0 +1,81 @@
+use actix_session::SessionInsertError;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::ContentType,
+    middleware::Next,
+    FromRequest, HttpResponse,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+use crate::{session_state::TypedSession, utils::peek_body};
+
+#[derive(serde::Deserialize)]
+struct CsrfFormData {
+    csrf_token: Option<String>,
+}
+
+/// The synchronizer token of the session, created on first use.
+///
+/// Every form that changes state must embed it in a hidden `csrf_token` field.
+pub fn csrf_token(session: &TypedSession) -> Result<String, actix_web::Error> {
+    if let Some(token) = session
+        .get_csrf_token()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Ok(token);
+    }
+    rotate_csrf_token(session).map_err(actix_web::error::ErrorInternalServerError)
+}
+
+/// Replace the token of the session, e.g. when a user logs in.
+pub fn rotate_csrf_token(session: &TypedSession) -> Result<String, SessionInsertError> {
+    let token: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect();
+    session.insert_csrf_token(&token)?;
+    Ok(token)
+}
+
+/// Reject state-changing requests whose form does not carry the token of the session.
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if req.method().is_safe() {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session
+        .get_csrf_token()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let body = peek_body(&mut req).await?;
+    let submitted = serde_urlencoded::from_bytes::<CsrfFormData>(&body)
+        .ok()
+        .and_then(|f| f.csrf_token);
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted)) if tokens_match(&expected, &submitted) => {
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        }
+        _ => {
+            tracing::warn!(path = %req.path(), "Rejected a request with a missing or invalid CSRF token");
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::html())
+                .body(include_str!("invalid_csrf_token.html"));
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+/// Compare digests rather than the tokens themselves, so that timing reveals nothing.
+fn tokens_match(expected: &str, submitted: &str) -> bool {
+    Sha256::digest(expected.as_bytes()) == Sha256::digest(submitted.as_bytes())
+}
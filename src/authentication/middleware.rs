@@ -1,11 +1,20 @@
 use std::ops::Deref;
 
 use actix_web::{
-    body::MessageBody, dev::{ServiceRequest, ServiceResponse}, middleware::Next, FromRequest, HttpMessage
+    body::{EitherBody, MessageBody}, dev::{ServiceRequest, ServiceResponse}, http::header::{ContentType, AUTHORIZATION, WWW_AUTHENTICATE}, middleware::Next, web, FromRequest, HttpMessage, HttpResponse
 };
+use actix_web_flash_messages::FlashMessage;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::{session_state::TypedSession, utils::see_other};
+use sqlx::PgPool;
+
+use crate::{configuration::SessionSettings, session_registry::SessionRegistry, session_state::TypedSession, utils::see_other};
+
+use super::{authenticate_api_token, get_user_status, Role};
+
+/// The pages a user who must choose a new password can still get to.
+const PASSWORD_RESET_PATHS: [&str; 2] = ["/admin/password", "/admin/logout"];
 
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
@@ -27,15 +36,62 @@ impl Deref for UserId {
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
-) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
     match session.get_user_id().map_err(actix_web::error::ErrorInternalServerError)? {
         Some(user_id) => {
+            let registry = req
+                .app_data::<web::Data<SessionRegistry>>()
+                .cloned()
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing session registry"))?;
+            let session_id = session.get_session_id().map_err(actix_web::error::ErrorInternalServerError)?;
+            let settings = req
+                .app_data::<web::Data<SessionSettings>>()
+                .cloned()
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing session settings"))?;
+            let now = Utc::now();
+            if let Some(message) = session_timeout(&session, &settings, now)? {
+                if let Some(session_id) = session_id {
+                    registry.revoke(user_id, session_id).await.map_err(actix_web::error::ErrorInternalServerError)?;
+                }
+                session.logout();
+                FlashMessage::info(message).send();
+                return Ok(req.into_response(see_other("/login")).map_into_right_body());
+            }
+            // Sessions can be revoked, e.g. when the password is reset
+            let is_registered = match session_id {
+                Some(session_id) => {
+                    registry.touch(session_id).await.map_err(actix_web::error::ErrorInternalServerError)?
+                }
+                None => false,
+            };
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .cloned()
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing database pool"))?;
+            let status = get_user_status(&pool, user_id).await.map_err(actix_web::error::ErrorInternalServerError)?;
+            let is_active = status.as_ref().is_some_and(|s| s.active);
+            if !is_registered || !is_active {
+                // Respond rather than fail, so that the session and flash middlewares
+                // get to update their cookies
+                session.logout();
+                FlashMessage::info("Your session has ended, please log in again").send();
+                return Ok(req.into_response(see_other("/login")).map_into_right_body());
+            }
+            let Some(status) = status else {
+                return Err(actix_web::error::ErrorInternalServerError("The user does not exist"));
+            };
+            if status.password_reset_required && !PASSWORD_RESET_PATHS.contains(&req.path()) {
+                FlashMessage::info("You must choose a new password before going any further").send();
+                return Ok(req.into_response(see_other("/admin/password")).map_into_right_body());
+            }
+            session.insert_last_seen_at(now).map_err(actix_web::error::ErrorInternalServerError)?;
             req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
+            req.extensions_mut().insert(status.role);
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
         },
         None => {
             let response = see_other("/login");
@@ -45,3 +101,95 @@ pub async fn reject_anonymous_users(
     }
 }
 
+/// Authenticate `/api` requests with the `Authorization: Bearer` header, in place of a session.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim().to_string());
+    let caller = match token {
+        Some(token) => {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .cloned()
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing database pool"))?;
+            authenticate_api_token(&pool, &token)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?
+        }
+        None => None,
+    };
+    let Some(caller) = caller else {
+        let response = HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, r#"Bearer realm="api""#))
+            .json(serde_json::json!({ "error": "A valid API token is required" }));
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+    req.extensions_mut().insert(UserId(caller.user_id));
+    req.extensions_mut().insert(caller);
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// Why the session has timed out, if it has.
+fn session_timeout(
+    session: &TypedSession,
+    settings: &SessionSettings,
+    now: DateTime<Utc>,
+) -> Result<Option<&'static str>, actix_web::Error> {
+    let logged_in_at = session.get_logged_in_at().map_err(actix_web::error::ErrorInternalServerError)?;
+    let last_seen_at = session.get_last_seen_at().map_err(actix_web::error::ErrorInternalServerError)?;
+    let (Some(logged_in_at), Some(last_seen_at)) = (logged_in_at, last_seen_at) else {
+        // Sessions started before timeouts were enforced
+        return Ok(Some("Your session has expired, please log in again"));
+    };
+    if now - logged_in_at > settings.absolute_timeout() {
+        return Ok(Some("Your session has expired, please log in again"));
+    }
+    if now - last_seen_at > settings.idle_timeout() {
+        return Ok(Some("You have been logged out after a period of inactivity"));
+    }
+    Ok(None)
+}
+
+/// Only let editors and owners through. Must run within `reject_anonymous_users`.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only let owners through. Must run within `reject_anonymous_users`.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+async fn require_role(
+    required: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let role = req
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("The role of the user is unknown"))?;
+    if role.allows(required) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    tracing::warn!(%role, %required, path = %req.path(), "Denied access to an admin page");
+    FlashMessage::error(format!("You need the {} role to do this", required)).send();
+    let response = HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(include_str!("forbidden.html"));
+    Ok(req.into_response(response).map_into_right_body())
+}
@@ -1,5 +1,21 @@
+mod api_tokens;
+mod csrf;
 mod middleware;
+mod oidc;
 mod password;
+mod password_reset;
+mod roles;
+mod throttle;
+mod two_factor;
+mod users;
 
+pub use api_tokens::*;
+pub use csrf::*;
 pub use password::*;
+pub use password_reset::*;
+pub use roles::*;
 pub use middleware::*;
+pub use oidc::*;
+pub use throttle::*;
+pub use two_factor::*;
+pub use users::*;
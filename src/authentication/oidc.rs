@@ -0,0 +1,415 @@
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::compute_password_hash;
+use crate::configuration::{OidcSettings, PasswordHashingSettings};
+use crate::domain::{SubscriberEmail, Username};
+
+const OIDC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// Tolerate this much clock skew between us and the provider when checking `exp`.
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// What has to survive the trip to the identity provider and back, kept in the session.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PendingOidcLogin {
+    pub state: String,
+    pub nonce: String,
+    /// The PKCE secret, whose hash went out with the authorization request
+    pub code_verifier: String,
+}
+
+/// The claims of a verified ID token.
+#[derive(serde::Deserialize, Debug)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+impl IdTokenClaims {
+    /// The email address, if the provider vouches for it.
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+struct JsonWebKeySet {
+    keys: Vec<JsonWebKey>,
+}
+
+#[derive(serde::Deserialize)]
+struct JsonWebKey {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct IdTokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Logs admins in with an OpenID Connect provider, using the authorization code flow with PKCE.
+pub struct OidcClient {
+    http_client: reqwest::Client,
+    settings: OidcSettings,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(OIDC_TIMEOUT)
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            settings,
+        }
+    }
+
+    pub fn settings(&self) -> &OidcSettings {
+        &self.settings
+    }
+
+    #[tracing::instrument(name = "Discover OIDC provider", skip(self))]
+    async fn metadata(&self) -> Result<ProviderMetadata, anyhow::Error> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.settings.issuer_url.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http_client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to fetch the OIDC provider metadata")?
+            .json()
+            .await
+            .context("Invalid OIDC provider metadata")?;
+        if metadata.issuer != self.settings.issuer_url {
+            anyhow::bail!("The OIDC provider metadata is for another issuer: {}", metadata.issuer);
+        }
+        Ok(metadata)
+    }
+
+    /// Where to send the user to log in, and what to remember until they come back.
+    #[tracing::instrument(name = "Start OIDC login", skip(self))]
+    pub async fn start_login(
+        &self,
+        redirect_uri: &str,
+    ) -> Result<(String, PendingOidcLogin), anyhow::Error> {
+        let metadata = self.metadata().await?;
+        let pending = PendingOidcLogin {
+            state: random_string(32),
+            nonce: random_string(32),
+            code_verifier: random_string(64),
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.code_verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.settings.client_id),
+                ("redirect_uri", redirect_uri),
+                ("scope", "openid email"),
+                ("state", &pending.state),
+                ("nonce", &pending.nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("Invalid OIDC authorization endpoint")?;
+        Ok((url.to_string(), pending))
+    }
+
+    /// Trade the authorization code for an ID token, and verify it.
+    #[tracing::instrument(name = "Finish OIDC login", skip_all)]
+    pub async fn finish_login(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        pending: &PendingOidcLogin,
+    ) -> Result<IdTokenClaims, anyhow::Error> {
+        let metadata = self.metadata().await?;
+        let tokens: TokenResponse = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("client_id", &self.settings.client_id),
+                ("client_secret", &self.settings.client_secret),
+                ("code_verifier", &pending.code_verifier),
+            ])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to exchange the authorization code")?
+            .json()
+            .await
+            .context("Invalid OIDC token response")?;
+        let keys: JsonWebKeySet = self
+            .http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to fetch the OIDC signing keys")?
+            .json()
+            .await
+            .context("Invalid OIDC signing keys")?;
+        verify_id_token(
+            &tokens.id_token,
+            &keys,
+            &self.settings.issuer_url,
+            &self.settings.client_id,
+            &pending.nonce,
+        )
+    }
+}
+
+fn random_string(length: usize) -> String {
+    thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+/// Check the RS256 signature and the claims of an ID token.
+fn verify_id_token(
+    id_token: &str,
+    keys: &JsonWebKeySet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, anyhow::Error> {
+    let mut parts = id_token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("The ID token is not a JWT");
+    };
+    let header: IdTokenHeader = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header)?)
+        .context("Invalid ID token header")?;
+    if header.alg != "RS256" {
+        anyhow::bail!("Unsupported ID token algorithm: {}", header.alg);
+    }
+    let key = keys
+        .keys
+        .iter()
+        .filter(|k| k.kty == "RSA")
+        .find(|k| header.kid.is_none() || k.kid == header.kid)
+        .context("No key matches the ID token")?;
+    let (Some(n), Some(e)) = (&key.n, &key.e) else {
+        anyhow::bail!("Incomplete RSA key");
+    };
+    let public_key = RsaPublicKeyComponents {
+        n: URL_SAFE_NO_PAD.decode(n)?,
+        e: URL_SAFE_NO_PAD.decode(e)?,
+    };
+    let signed = &id_token[..header_and_payload_length(id_token)];
+    public_key
+        .verify(
+            &RSA_PKCS1_2048_8192_SHA256,
+            signed.as_bytes(),
+            &URL_SAFE_NO_PAD.decode(signature)?,
+        )
+        .map_err(|_| anyhow::anyhow!("Invalid ID token signature"))?;
+
+    let claims: IdTokenClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+        .context("Invalid ID token claims")?;
+    if claims.iss != issuer {
+        anyhow::bail!("The ID token was issued by {}", claims.iss);
+    }
+    if !claims.aud.contains(client_id) {
+        anyhow::bail!("The ID token is meant for another client");
+    }
+    if claims.exp + CLOCK_SKEW_SECONDS < Utc::now().timestamp() {
+        anyhow::bail!("The ID token has expired");
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        anyhow::bail!("The ID token nonce does not match");
+    }
+    Ok(claims)
+}
+
+/// The signature covers everything up to the second dot.
+fn header_and_payload_length(jwt: &str) -> usize {
+    jwt.rfind('.').unwrap_or(jwt.len())
+}
+
+pub enum OidcUser {
+    Active(Uuid),
+    Deactivated,
+    /// No account matches, and none could be provisioned
+    Unknown,
+}
+
+/// Find the user an identity belongs to, by subject then by verified email.
+///
+/// Matching by email links the identity to the account, so later logins match by subject.
+/// The email must be verified on both sides: an address users set for themselves is never
+/// matched, or anyone could claim the identity behind it.
+#[tracing::instrument(name = "Find OIDC user", skip(pool, claims, settings, hashing), fields(sub = %claims.sub))]
+pub async fn find_or_provision_oidc_user(
+    pool: &PgPool,
+    claims: &IdTokenClaims,
+    settings: &OidcSettings,
+    hashing: &PasswordHashingSettings,
+) -> Result<OidcUser, anyhow::Error> {
+    let user = sqlx::query!(
+        "SELECT user_id, active FROM users WHERE oidc_subject = $1",
+        claims.sub,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by OIDC subject")?;
+    if let Some(user) = user {
+        return Ok(active_or_not(user.user_id, user.active));
+    }
+    let Some(email) = claims.verified_email() else {
+        return Ok(OidcUser::Unknown);
+    };
+
+    let user = sqlx::query!(
+        r#"
+        UPDATE users
+        SET oidc_subject = $1
+        WHERE lower(email) = lower($2) AND email_verified AND oidc_subject IS NULL
+        RETURNING user_id, active
+        "#,
+        claims.sub,
+        email,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to link a user to an OIDC subject")?;
+    if let Some(user) = user {
+        return Ok(active_or_not(user.user_id, user.active));
+    }
+    if !settings.auto_provision {
+        return Ok(OidcUser::Unknown);
+    }
+    // The claim becomes the username, so it has to be a plain email address
+    let Some((email, username)) = SubscriberEmail::parse(email.to_string())
+        .ok()
+        .and_then(|email| Username::parse(email.as_ref()).ok().map(|username| (email, username)))
+    else {
+        tracing::warn!("Refused to provision a user with an invalid email claim");
+        return Ok(OidcUser::Unknown);
+    };
+
+    // The account is only meant for single sign-on, but needs a password hash all the same
+    let password = random_string(64);
+    let hashing = hashing.clone();
+    let password_hash = crate::telemetry::spawn_blocking_with_tracing(move || {
+        compute_password_hash(password, &hashing)
+    })
+    .await
+    .context("Failed to spawn blocking task")?
+    .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, email_verified, role, oidc_subject)
+        VALUES ($1, $2, $3, $4, true, $5, $6)
+        "#,
+        user_id,
+        username.as_ref(),
+        password_hash,
+        email.as_ref(),
+        settings.default_role.as_str(),
+        claims.sub,
+    )
+    .execute(pool)
+    .await;
+    match result {
+        Ok(_) => {
+            tracing::info!(%user_id, role = %settings.default_role, "Provisioned a user from single sign-on");
+            Ok(OidcUser::Active(user_id))
+        }
+        // e.g. the email is the username of another account
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            tracing::warn!(
+                constraint = e.constraint(),
+                "Could not provision a user from single sign-on, the account would clash with an existing one"
+            );
+            Ok(OidcUser::Unknown)
+        }
+        Err(e) => Err(anyhow::Error::new(e).context("Failed to provision a user")),
+    }
+}
+
+fn active_or_not(user_id: Uuid, active: bool) -> OidcUser {
+    if active {
+        OidcUser::Active(user_id)
+    } else {
+        OidcUser::Deactivated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header_and_payload_length, Audience};
+
+    #[test]
+    fn the_signature_covers_the_header_and_payload() {
+        let jwt = "aGVhZGVy.cGF5bG9hZA.c2lnbmF0dXJl";
+        assert_eq!(&jwt[..header_and_payload_length(jwt)], "aGVhZGVy.cGF5bG9hZA");
+    }
+
+    #[test]
+    fn audiences_can_be_a_string_or_a_list() {
+        let one: Audience = serde_json::from_str(r#""client""#).unwrap();
+        let many: Audience = serde_json::from_str(r#"["other", "client"]"#).unwrap();
+        assert!(one.contains("client"));
+        assert!(many.contains("client"));
+        assert!(!many.contains("another"));
+    }
+}
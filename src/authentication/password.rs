@@ -3,9 +3,10 @@ use argon2::PasswordHasher;
 use argon2::{password_hash::SaltString, Algorithm, Params, PasswordVerifier, Version};
 use argon2::{Argon2, PasswordHash};
 use sqlx::PgPool;
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::{configuration::PasswordHashingSettings, domain::AdminPassword, telemetry::spawn_blocking_with_tracing};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -20,16 +21,50 @@ pub struct Credentials {
     pub password: String,
 }
 
-#[tracing::instrument(name = "Validate user credentials", skip(pool, credentials))]
+/// The hash of the `admin` user inserted by the `add_seed_user` migration.
+///
+/// It is public, so whoever knows the matching password can log in wherever it is still in use.
+pub const SEED_ADMIN_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$w9RJhR+rgvtN8KrWVxxtqg$9TzJS1ZoiO4Wo+xqLu3fWsM74fFP9h2RQSV5SfnmQMg";
+
+/// Whether any user still has the well-known seed credentials.
+#[tracing::instrument(name = "Check for seed credentials", skip(pool))]
+pub async fn seed_credentials_present(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT 1 AS "present!" FROM users WHERE password_hash = $1 AND active"#,
+        SEED_ADMIN_PASSWORD_HASH,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look for the seed credentials")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Validate user credentials", skip(pool, credentials, hashing))]
 pub async fn validate_credentials(
     pool: &PgPool,
     credentials: Credentials,
+    hashing: &PasswordHashingSettings,
 ) -> Result<uuid::Uuid, AuthError> {
-    let (user_id, expected_password_hash) = get_stored_credentials(pool, &credentials.username)
-        .await
-        .map_err(AuthError::UnexpectedError)?
-        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")))?;
+    let Some((user_id, expected_password_hash)) =
+        get_stored_credentials(pool, &credentials.username)
+            .await
+            .map_err(AuthError::UnexpectedError)?
+    else {
+        // Unknown usernames cost as much as known ones, so that they cannot be
+        // told apart by response time
+        let hashing = hashing.clone();
+        spawn_blocking_with_tracing(move || compute_password_hash(credentials.password, &hashing))
+            .await
+            .context("Failed to spawn blocking task")?
+            .context("Failed to hash password")?;
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")));
+    };
 
+    let needs_rehash = needs_rehash(&expected_password_hash, hashing)?;
+    // Only keep a copy of the password around when it is going to be hashed again
+    let password = needs_rehash.then(|| credentials.password.clone());
+    let stored_password_hash = expected_password_hash.clone();
     spawn_blocking_with_tracing(move || {
         tracing::info_span!("Verify password hash").in_scope(|| {
             let expected_password_hash = PasswordHash::new(&expected_password_hash)
@@ -46,21 +81,86 @@ pub async fn validate_credentials(
     .context("Failed to spawn blocking task")
     .map_err(AuthError::UnexpectedError)??;
 
+    if let Some(password) = password {
+        tokio::spawn(
+            rehash_password(pool.clone(), user_id, stored_password_hash, password, hashing.clone())
+                .instrument(tracing::Span::current()),
+        );
+    }
     Ok(user_id)
 }
 
-#[tracing::instrument(name = "Change user password", skip(pool, user_id, password))]
+/// Whether a stored hash was made with another algorithm or other parameters than
+/// the configured ones.
+fn needs_rehash(
+    password_hash: &str,
+    hashing: &PasswordHashingSettings,
+) -> Result<bool, anyhow::Error> {
+    let password_hash =
+        PasswordHash::new(password_hash).context("Failed to parse hash in PHC string format")?;
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return Ok(true);
+    }
+    let params = Params::try_from(&password_hash).context("Failed to read the hash parameters")?;
+    Ok(params.m_cost() != hashing.memory_kib
+        || params.t_cost() != hashing.iterations
+        || params.p_cost() != hashing.parallelism)
+}
+
+/// Replace an outdated hash, unless the password has been changed in the meantime.
+#[tracing::instrument(name = "Rehash password", skip(pool, old_password_hash, password, hashing))]
+async fn rehash_password(
+    pool: PgPool,
+    user_id: Uuid,
+    old_password_hash: String,
+    password: String,
+    hashing: PasswordHashingSettings,
+) {
+    let outcome: Result<(), anyhow::Error> = async {
+        let password_hash =
+            spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+                .await?
+                .context("Failed to hash password")?;
+        sqlx::query!(
+            r#"UPDATE users
+            SET password_hash = $1
+            WHERE user_id = $2 AND password_hash = $3"#,
+            password_hash,
+            user_id,
+            old_password_hash,
+        )
+        .execute(&pool)
+        .await
+        .context("Failed to store the new password hash")?;
+        Ok(())
+    }
+    .await;
+    match outcome {
+        Ok(()) => tracing::info!("Upgraded an outdated password hash"),
+        Err(e) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to upgrade an outdated password hash",
+        ),
+    }
+}
+
+#[tracing::instrument(name = "Change user password", skip(pool, user_id, password, hashing))]
 pub async fn change_password(
     pool: &PgPool,
     user_id: Uuid,
-    password: String,
+    password: AdminPassword,
+    hashing: &PasswordHashingSettings,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+    let hashing = hashing.clone();
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password.into_inner(), &hashing))
         .await?
         .context("Failed to hash password")?;
     sqlx::query!(
         r#"UPDATE users
-        SET password_hash = $1
+        SET password_hash = $1, password_reset_required = false
         WHERE user_id = $2"#,
         password_hash,
         user_id,
@@ -71,18 +171,20 @@ pub async fn change_password(
     Ok(())
 }
 
-fn compute_password_hash(password: String) -> Result<String, anyhow::Error> {
+pub fn compute_password_hash(
+    password: String,
+    hashing: &PasswordHashingSettings,
+) -> Result<String, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
-    )
-    .hash_password(password.as_bytes(), &salt)?
-    .to_string();
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, hashing.params()?)
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string();
     Ok(password_hash)
 }
 
+/// Deactivated users are treated as unknown ones, and so are users still on the seed
+/// credentials: they cannot log in, and their hash is never upgraded, so that
+/// [`seed_credentials_present`] keeps finding them.
 #[tracing::instrument(name = "Fetch user credentials", skip(pool, username))]
 async fn get_stored_credentials(
     pool: &PgPool,
@@ -92,9 +194,10 @@ async fn get_stored_credentials(
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND active AND password_hash <> $2
         "#,
         username,
+        SEED_ADMIN_PASSWORD_HASH,
     )
     .fetch_optional(pool)
     .await
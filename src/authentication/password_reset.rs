@@ -0,0 +1,125 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The active user an email address belongs to, ignoring case.
+#[tracing::instrument(name = "Find user by email", skip(pool, email))]
+pub async fn find_user_by_email(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, email AS "email!"
+        FROM users
+        WHERE lower(email) = lower($1) AND active
+        "#,
+        email.trim(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email")?;
+    Ok(row.map(|r| (r.user_id, r.email)))
+}
+
+/// Create a reset token for `user_id`, valid for `ttl`.
+///
+/// Only a hash of the token is stored.
+#[tracing::instrument(name = "Create password reset token", skip(pool))]
+pub async fn create_password_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<String, anyhow::Error> {
+    let token: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_reset_token(&token),
+        user_id,
+        Utc::now() + ttl,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token")?;
+    Ok(token)
+}
+
+/// The username of the user `token` was issued to, if it can still be used to reset a password.
+#[tracing::instrument(name = "Check password reset token", skip_all)]
+pub async fn get_password_reset_username(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT u.username
+        FROM password_reset_tokens t
+        JOIN users u ON u.user_id = t.user_id
+        WHERE t.token_hash = $1 AND t.used_at IS NULL AND t.expires_at > now()
+        "#,
+        hash_reset_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a password reset token")?;
+    Ok(row.map(|r| r.username))
+}
+
+/// Use up `token`, returning the user it was issued to if it was still valid.
+///
+/// Every other outstanding token of that user is used up as well.
+#[tracing::instrument(name = "Consume password reset token", skip_all)]
+pub async fn consume_password_reset_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")?;
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_reset_token(token),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to use up a password reset token")?
+    .map(|r| r.user_id);
+    if let Some(user_id) = user_id {
+        sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = now()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to use up other password reset tokens")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a password reset")?;
+    Ok(user_id)
+}
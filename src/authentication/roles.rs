@@ -0,0 +1,80 @@
+/// What a user is allowed to do under `/admin`, each role including the ones below it.
+///
+/// - viewers can browse subscribers and manage their own account;
+/// - editors can also publish newsletters and change subscriptions;
+/// - owners can also manage users.
+#[derive(serde::Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Editor, Role::Owner];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Whether this role grants everything `required` does.
+    pub fn allows(&self, required: Role) -> bool {
+        *self >= required
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            other => Err(format!("{} is not a valid role", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn roles_include_the_ones_below_them() {
+        assert!(Role::Owner.allows(Role::Editor));
+        assert!(Role::Owner.allows(Role::Viewer));
+        assert!(Role::Editor.allows(Role::Viewer));
+        assert!(Role::Editor.allows(Role::Editor));
+    }
+
+    #[test]
+    fn roles_do_not_include_the_ones_above_them() {
+        assert!(!Role::Viewer.allows(Role::Editor));
+        assert!(!Role::Editor.allows(Role::Owner));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in Role::ALL {
+            assert_ok_eq!(Role::try_from(role.as_str().to_string()), role);
+        }
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        assert_err!(Role::try_from("admin".to_string()));
+    }
+}
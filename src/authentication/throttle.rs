@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::configuration::LoginThrottleSettings;
+
+pub enum LoginThrottleDecision {
+    /// Go ahead with the attempt once `delay` has elapsed.
+    /// `unlocked` is set when a lockout has just come to an end.
+    Proceed { delay: Duration, unlocked: bool },
+    LockedOut { retry_after: Duration },
+}
+
+/// Failed login attempts, per username and per client address, kept in Redis.
+///
+/// Usernames are tracked whether they exist or not, so that the throttling does
+/// not reveal which ones do.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    connection: ConnectionManager,
+    key_prefix: String,
+    settings: LoginThrottleSettings,
+}
+
+struct Subject {
+    failures_key: String,
+    lockout_key: String,
+    lockout_threshold: u64,
+}
+
+impl LoginThrottle {
+    pub fn new(
+        connection: ConnectionManager,
+        key_prefix: String,
+        settings: LoginThrottleSettings,
+    ) -> Self {
+        Self {
+            connection,
+            key_prefix,
+            settings,
+        }
+    }
+
+    fn subjects(&self, username: &str, ip: &str) -> [Subject; 2] {
+        let subject = |kind: &str, value: &str, lockout_threshold: u64| Subject {
+            failures_key: format!("{}:login_failures:{}:{}", self.key_prefix, kind, value),
+            lockout_key: format!("{}:login_lockout:{}:{}", self.key_prefix, kind, value),
+            lockout_threshold,
+        };
+        [
+            subject("username", username, self.settings.username_lockout_threshold),
+            subject("ip", ip, self.settings.ip_lockout_threshold),
+        ]
+    }
+
+    #[tracing::instrument(name = "Check login throttling", skip(self, username))]
+    pub async fn check(
+        &self,
+        username: &str,
+        ip: &str,
+    ) -> Result<LoginThrottleDecision, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let now = Utc::now().timestamp();
+        let mut retry_after = None;
+        let mut unlocked = false;
+        let mut failures = 0;
+        for subject in self.subjects(username, ip) {
+            let (locked_until, subject_failures): (Option<i64>, Option<u64>) = redis::pipe()
+                .get(&subject.lockout_key)
+                .get(&subject.failures_key)
+                .query_async(&mut connection)
+                .await
+                .context("Failed to read login throttling state")?;
+            failures = failures.max(subject_failures.unwrap_or(0));
+            match locked_until {
+                Some(until) if until > now => {
+                    let remaining = Duration::from_secs((until - now) as u64);
+                    retry_after = retry_after.max(Some(remaining));
+                }
+                Some(_) => {
+                    // Only one of the concurrent attempts gets to report the unlock
+                    let deleted: u64 = connection
+                        .del(&subject.lockout_key)
+                        .await
+                        .context("Failed to clear an expired lockout")?;
+                    if deleted > 0 {
+                        tracing::info!(lockout = %subject.lockout_key, "Login lockout expired");
+                        unlocked = true;
+                    }
+                }
+                None => {}
+            }
+        }
+        match retry_after {
+            Some(retry_after) => Ok(LoginThrottleDecision::LockedOut { retry_after }),
+            None => Ok(LoginThrottleDecision::Proceed {
+                delay: self.delay_after(failures),
+                unlocked,
+            }),
+        }
+    }
+
+    /// Progressive delay: nothing for the first few failures, then doubling up to a cap.
+    fn delay_after(&self, failures: u64) -> Duration {
+        if failures < self.settings.free_attempts {
+            return Duration::ZERO;
+        }
+        let exponent = (failures - self.settings.free_attempts).min(16) as u32;
+        let delay = self
+            .settings
+            .base_delay_milliseconds
+            .saturating_mul(2u64.pow(exponent))
+            .min(self.settings.max_delay_milliseconds);
+        Duration::from_millis(delay)
+    }
+
+    /// Count a failed attempt, returning the lockout it triggered, if any.
+    #[tracing::instrument(name = "Record failed login", skip(self, username))]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        ip: &str,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let lockout = Duration::from_secs(self.settings.lockout_seconds);
+        let mut locked_out = None;
+        for subject in self.subjects(username, ip) {
+            let (failures,): (u64,) = redis::pipe()
+                .atomic()
+                .incr(&subject.failures_key, 1)
+                .expire(&subject.failures_key, self.settings.failure_window_seconds as i64)
+                .ignore()
+                .query_async(&mut connection)
+                .await
+                .context("Failed to count a failed login")?;
+            if failures >= subject.lockout_threshold {
+                let locked_until = Utc::now().timestamp() + lockout.as_secs() as i64;
+                // Keep the key around past the lockout, to notice when it ends
+                let ttl = self.settings.lockout_seconds + self.settings.failure_window_seconds;
+                redis::pipe()
+                    .atomic()
+                    .set_ex(&subject.lockout_key, locked_until, ttl)
+                    .ignore()
+                    .del(&subject.failures_key)
+                    .ignore()
+                    .query_async::<()>(&mut connection)
+                    .await
+                    .context("Failed to store a lockout")?;
+                tracing::warn!(
+                    lockout = %subject.lockout_key,
+                    failures,
+                    "Locking out logins after repeated failures"
+                );
+                locked_out = Some(lockout);
+            }
+        }
+        Ok(locked_out)
+    }
+
+    /// Forget the failures of a user who has just logged in.
+    #[tracing::instrument(name = "Record successful login", skip(self, username))]
+    pub async fn record_success(&self, username: &str) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let [user, _] = self.subjects(username, "");
+        connection
+            .del::<_, ()>(&user.failures_key)
+            .await
+            .context("Failed to clear failed logins")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+const TOTP_ISSUER: &str = "zero2prod";
+const TOTP_STEP_SECONDS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+pub enum TwoFactorStatus {
+    Disabled,
+    /// Enrolment has started, but no code has been confirmed yet
+    Pending { secret: String },
+    Enabled { recovery_codes_left: i64 },
+}
+
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode { recovery_codes_left: i64 },
+}
+
+/// RFC 6238 parameters understood by every authenticator app: SHA-1, 6 digits, 30 seconds.
+///
+/// Clock skew is handled by `matching_step`, so that we know which step a code belongs to.
+pub fn build_totp(secret: &str, username: &str) -> Result<TOTP, anyhow::Error> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {:?}", e))?;
+    Ok(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        username.to_string(),
+    ))
+}
+
+/// The time step `code` belongs to, accepting one step before and after the current one.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = Utc::now().timestamp() as u64;
+    let current_step = now / TOTP_STEP_SECONDS;
+    (current_step.saturating_sub(1)..=current_step + 1)
+        .find(|step| totp.check(code, step * TOTP_STEP_SECONDS))
+        .map(|step| step as i64)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_uppercase().as_bytes()))
+}
+
+#[tracing::instrument(name = "Get two-factor status", skip(pool))]
+pub async fn get_two_factor_status(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<TwoFactorStatus, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            secret,
+            enabled_at,
+            (
+                SELECT count(*) FROM user_recovery_codes c
+                WHERE c.user_id = t.user_id AND c.used_at IS NULL
+            ) AS "recovery_codes_left!"
+        FROM user_totp t
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the two-factor status")?;
+    Ok(match row {
+        None => TwoFactorStatus::Disabled,
+        Some(r) if r.enabled_at.is_none() => TwoFactorStatus::Pending { secret: r.secret },
+        Some(r) => TwoFactorStatus::Enabled {
+            recovery_codes_left: r.recovery_codes_left,
+        },
+    })
+}
+
+pub async fn is_two_factor_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    Ok(matches!(
+        get_two_factor_status(pool, user_id).await?,
+        TwoFactorStatus::Enabled { .. }
+    ))
+}
+
+/// Generate a new secret, replacing any enrolment that has not been confirmed.
+///
+/// Returns `None` if two-factor authentication is already enabled.
+#[tracing::instrument(name = "Start two-factor enrolment", skip(pool))]
+pub async fn start_two_factor_enrolment(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+    let secret = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO user_totp (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, last_used_step = NULL
+        WHERE user_totp.enabled_at IS NULL
+        "#,
+        user_id,
+        secret,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new TOTP secret")?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(secret))
+}
+
+/// Enable two-factor authentication if `code` matches the pending secret.
+///
+/// Returns the recovery codes, which are only stored hashed and cannot be shown again.
+#[tracing::instrument(name = "Confirm two-factor enrolment", skip(pool, code))]
+pub async fn confirm_two_factor_enrolment(
+    pool: &PgPool,
+    user_id: Uuid,
+    username: &str,
+    code: &str,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let TwoFactorStatus::Pending { secret } = get_two_factor_status(pool, user_id).await? else {
+        return Ok(None);
+    };
+    let Some(step) = matching_step(&build_totp(&secret, username)?, code.trim()) else {
+        return Ok(None);
+    };
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            thread_rng()
+                .sample_iter(Alphanumeric)
+                .map(|c| char::from(c).to_ascii_uppercase())
+                .take(RECOVERY_CODE_LENGTH)
+                .collect()
+        })
+        .collect();
+    let code_hashes: Vec<String> = recovery_codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE user_totp
+        SET enabled_at = now(), last_used_step = $1
+        WHERE user_id = $2
+        "#,
+        step,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+    sqlx::query!("DELETE FROM user_recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete old recovery codes")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, unnest($2::text[])
+        "#,
+        user_id,
+        &code_hashes,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit two-factor enrolment")?;
+    Ok(Some(recovery_codes))
+}
+
+/// Check a code from an authenticator app, or an unused recovery code.
+///
+/// Accepted codes are burnt: a TOTP code cannot be used twice, nor can an older one.
+#[tracing::instrument(name = "Verify second factor", skip(pool, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    username: &str,
+    code: &str,
+) -> Result<Option<SecondFactor>, anyhow::Error> {
+    let secret = sqlx::query!(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND enabled_at IS NOT NULL",
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the TOTP secret")?
+    .map(|r| r.secret);
+    let Some(secret) = secret else {
+        return Ok(None);
+    };
+
+    if let Some(step) = matching_step(&build_totp(&secret, username)?, code.trim()) {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET last_used_step = $1
+            WHERE user_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)
+            "#,
+            step,
+            user_id,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the use of a TOTP code")?;
+        if result.rows_affected() == 1 {
+            return Ok(Some(SecondFactor::Totp));
+        }
+        return Ok(None);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of a recovery code")?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let recovery_codes_left = sqlx::query!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM user_recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count recovery codes")?
+    .count;
+    Ok(Some(SecondFactor::RecoveryCode { recovery_codes_left }))
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")?;
+    sqlx::query!("DELETE FROM user_recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete recovery codes")?;
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the TOTP secret")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit disabling two-factor authentication")?;
+    Ok(())
+}
@@ -0,0 +1,344 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{compute_password_hash, Role};
+use crate::{configuration::PasswordHashingSettings, domain::AdminPassword};
+
+pub struct UserRow {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub active: bool,
+    pub password_reset_required: bool,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct PendingInvitation {
+    pub email: String,
+    pub role: String,
+    pub invited_by: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What the authentication middleware needs to know about a logged-in user.
+pub struct UserStatus {
+    pub active: bool,
+    pub password_reset_required: bool,
+    pub role: Role,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CreateUserError {
+    #[error("The username {0} is already taken")]
+    UsernameTaken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcceptInvitationError {
+    #[error("This invitation is invalid or has expired")]
+    InvalidToken,
+    #[error("This username is already taken")]
+    UsernameTaken,
+    #[error("This email address is used by another account")]
+    EmailTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+fn hash_invitation_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(name = "List users", skip(pool))]
+pub async fn list_users(pool: &PgPool) -> Result<Vec<UserRow>, anyhow::Error> {
+    sqlx::query_as!(
+        UserRow,
+        r#"
+        SELECT user_id, username, email, active, password_reset_required, role, created_at
+        FROM users
+        ORDER BY created_at, username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch users")
+}
+
+#[tracing::instrument(name = "Get user status", skip(pool))]
+pub async fn get_user_status(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<UserStatus>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT active, password_reset_required, role FROM users WHERE user_id = $1",
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the status of a user")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(UserStatus {
+        active: row.active,
+        password_reset_required: row.password_reset_required,
+        role: Role::try_from(row.role).map_err(anyhow::Error::msg)?,
+    }))
+}
+
+#[tracing::instrument(name = "Create user", skip(pool, password, hashing))]
+pub async fn create_user(
+    pool: &PgPool,
+    username: &str,
+    password: AdminPassword,
+    role: Role,
+    hashing: &PasswordHashingSettings,
+) -> Result<Uuid, CreateUserError> {
+    let hashing = hashing.clone();
+    let password_hash = crate::telemetry::spawn_blocking_with_tracing(move || {
+        compute_password_hash(password.into_inner(), &hashing)
+    })
+    .await
+    .context("Failed to spawn blocking task")?
+    .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash,
+        role.as_str(),
+    )
+    .execute(pool)
+    .await;
+    match result {
+        Ok(_) => Ok(user_id),
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("users_username_key") => {
+            Err(CreateUserError::UsernameTaken(username.to_string()))
+        }
+        Err(e) => Err(anyhow::Error::new(e).context("Failed to create a user").into()),
+    }
+}
+
+/// Returns `false` if there is no such user.
+#[tracing::instrument(name = "Set user active", skip(transaction))]
+pub async fn set_user_active(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    active: bool,
+) -> Result<bool, anyhow::Error> {
+    let result = transaction
+        .execute(sqlx::query!(
+            "UPDATE users SET active = $1 WHERE user_id = $2",
+            active,
+            user_id,
+        ))
+        .await
+    .context("Failed to update the status of a user")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if there is no such user.
+#[tracing::instrument(name = "Set user role", skip(transaction))]
+pub async fn set_user_role(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    role: Role,
+) -> Result<bool, anyhow::Error> {
+    let result = transaction
+        .execute(sqlx::query!(
+            "UPDATE users SET role = $1 WHERE user_id = $2",
+            role.as_str(),
+            user_id,
+        ))
+        .await
+    .context("Failed to update the role of a user")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Make the user choose a new password the next time they log in.
+///
+/// Returns `false` if there is no such user.
+#[tracing::instrument(name = "Require password reset", skip(transaction))]
+pub async fn require_password_reset(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let result = transaction
+        .execute(sqlx::query!(
+            "UPDATE users SET password_reset_required = true WHERE user_id = $1",
+            user_id,
+        ))
+        .await
+    .context("Failed to require a password reset")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Whether any user, active or not, has this email address.
+#[tracing::instrument(name = "Check if an email is taken", skip(pool))]
+pub async fn is_user_email_taken(pool: &PgPool, email: &str) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT 1 AS "taken!" FROM users WHERE lower(email) = lower($1)"#,
+        email,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "List pending invitations", skip(pool))]
+pub async fn list_pending_invitations(
+    pool: &PgPool,
+) -> Result<Vec<PendingInvitation>, anyhow::Error> {
+    sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        SELECT i.email, i.role, u.username AS "invited_by?", i.expires_at
+        FROM user_invitations i
+        LEFT JOIN users u ON u.user_id = i.invited_by
+        WHERE i.accepted_at IS NULL AND i.expires_at > now()
+        ORDER BY i.created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch pending invitations")
+}
+
+/// Create an invitation for `email` to join as `role`, valid for `ttl`.
+///
+/// Only a hash of the token is stored.
+#[tracing::instrument(name = "Create invitation", skip(transaction))]
+pub async fn create_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    role: Role,
+    invited_by: Uuid,
+    ttl: chrono::Duration,
+) -> Result<String, anyhow::Error> {
+    let token: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect();
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO user_invitations (token_hash, email, role, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            hash_invitation_token(&token),
+            email,
+            role.as_str(),
+            invited_by,
+            Utc::now() + ttl,
+        ))
+        .await
+    .context("Failed to store an invitation")?;
+    Ok(token)
+}
+
+/// The email an invitation was sent to, if it can still be accepted.
+#[tracing::instrument(name = "Check invitation", skip_all)]
+pub async fn get_invitation_email(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email
+        FROM user_invitations
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        "#,
+        hash_invitation_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an invitation")?;
+    Ok(row.map(|r| r.email))
+}
+
+/// Create the account of an invited user, using up the invitation.
+#[tracing::instrument(name = "Accept invitation", skip(pool, token, password, hashing))]
+pub async fn accept_invitation(
+    pool: &PgPool,
+    token: &str,
+    username: &str,
+    password: AdminPassword,
+    hashing: &PasswordHashingSettings,
+) -> Result<Uuid, AcceptInvitationError> {
+    let hashing = hashing.clone();
+    let password_hash = crate::telemetry::spawn_blocking_with_tracing(move || {
+        compute_password_hash(password.into_inner(), &hashing)
+    })
+    .await
+    .context("Failed to spawn blocking task")?
+    .context("Failed to hash password")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")?;
+    let invitation = sqlx::query!(
+        r#"
+        UPDATE user_invitations
+        SET accepted_at = now()
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        RETURNING email, role
+        "#,
+        hash_invitation_token(token),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to use up an invitation")?
+    .ok_or(AcceptInvitationError::InvalidToken)?;
+
+    let user_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, email_verified, role)
+        VALUES ($1, $2, $3, $4, true, $5)
+        "#,
+        user_id,
+        username,
+        password_hash,
+        invitation.email,
+        invitation.role,
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("users_username_key") => {
+            return Err(AcceptInvitationError::UsernameTaken);
+        }
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("users_email_key") => {
+            return Err(AcceptInvitationError::EmailTaken);
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to create a user").into()),
+    }
+    sqlx::query!(
+        "UPDATE user_invitations SET accepted_user_id = $1 WHERE token_hash = $2",
+        user_id,
+        hash_invitation_token(token),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the user created from an invitation")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit accepting an invitation")?;
+    Ok(user_id)
+}
@@ -0,0 +1,157 @@
+use std::io::BufRead;
+
+use anyhow::Context;
+
+use crate::authentication::{create_user, Role};
+use crate::configuration::Settings;
+use crate::domain::AdminPassword;
+use crate::startup::get_connection_pool;
+use crate::subscriber_data::{erase_subscriber_data, export_subscriber_data};
+
+const USAGE: &str = "Usage:
+    zero2prod                                  Run the API and the delivery worker
+    zero2prod export-subscriber-data <email>   Print everything we hold about <email> as JSON
+    zero2prod erase-subscriber-data <email>    Delete everything we hold about <email>
+    zero2prod create-admin <username>          Create an owner, prompting for their password
+        [--password-stdin]                     Read the password from stdin instead";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    ExportSubscriberData { email: String },
+    EraseSubscriberData { email: String },
+    CreateAdmin { username: String, password_stdin: bool },
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, anyhow::Error> {
+        let args: Vec<String> = args.into_iter().collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] => Ok(Command::Serve),
+            ["export-subscriber-data", email] => Ok(Command::ExportSubscriberData {
+                email: email.trim().to_owned(),
+            }),
+            ["erase-subscriber-data", email] => Ok(Command::EraseSubscriberData {
+                email: email.trim().to_owned(),
+            }),
+            ["create-admin", username] => Ok(Command::CreateAdmin {
+                username: username.trim().to_owned(),
+                password_stdin: false,
+            }),
+            ["create-admin", username, "--password-stdin"] => Ok(Command::CreateAdmin {
+                username: username.trim().to_owned(),
+                password_stdin: true,
+            }),
+            _ => anyhow::bail!("{}", USAGE),
+        }
+    }
+}
+
+/// Run a one-off administrative command, writing its result to stdout.
+pub async fn run_command(command: Command, configuration: Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    match command {
+        Command::Serve => anyhow::bail!("`serve` is not a one-off command"),
+        Command::ExportSubscriberData { email } => {
+            let bundle = export_subscriber_data(&pool, &email).await?;
+            if bundle.is_empty() {
+                eprintln!("We do not hold any data about {}", email);
+            }
+            println!("{}", serde_json::to_string_pretty(&bundle)?);
+        }
+        Command::EraseSubscriberData { email } => {
+            let mut transaction = pool.begin().await?;
+            let report = erase_subscriber_data(&mut transaction, &email).await?;
+            transaction.commit().await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::CreateAdmin {
+            username,
+            password_stdin,
+        } => {
+            let password = if password_stdin {
+                read_password_from_stdin()?
+            } else {
+                prompt_for_password()?
+            };
+            let policy = configuration.password_policy.policy()?;
+            let password = AdminPassword::parse(password, &username, &policy)?;
+            let user_id = create_user(
+                &pool,
+                &username,
+                password,
+                Role::Owner,
+                &configuration.password_hashing,
+            )
+            .await?;
+            eprintln!("Created {} as an owner, with id {}", username, user_id);
+        }
+    }
+    Ok(())
+}
+
+fn read_password_from_stdin() -> Result<String, anyhow::Error> {
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Failed to read the password from stdin")?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn prompt_for_password() -> Result<String, anyhow::Error> {
+    let password = rpassword::prompt_password("Password: ").context("Failed to read the password")?;
+    let password_check =
+        rpassword::prompt_password("Confirm password: ").context("Failed to read the password")?;
+    if password != password_check {
+        anyhow::bail!("The passwords do not match");
+    }
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use claims::{assert_err, assert_ok_eq};
+
+    fn args(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn no_arguments_starts_the_server() {
+        assert_ok_eq!(Command::parse(args(&[])), Command::Serve);
+    }
+
+    #[test]
+    fn data_request_commands_take_an_email() {
+        assert_ok_eq!(
+            Command::parse(args(&["export-subscriber-data", "ursula@example.com"])),
+            Command::ExportSubscriberData { email: "ursula@example.com".into() }
+        );
+        assert_ok_eq!(
+            Command::parse(args(&["erase-subscriber-data", "ursula@example.com"])),
+            Command::EraseSubscriberData { email: "ursula@example.com".into() }
+        );
+    }
+
+    #[test]
+    fn create_admin_prompts_for_the_password_unless_told_to_read_stdin() {
+        assert_ok_eq!(
+            Command::parse(args(&["create-admin", "ursula"])),
+            Command::CreateAdmin { username: "ursula".into(), password_stdin: false }
+        );
+        assert_ok_eq!(
+            Command::parse(args(&["create-admin", "ursula", "--password-stdin"])),
+            Command::CreateAdmin { username: "ursula".into(), password_stdin: true }
+        );
+        assert_err!(Command::parse(args(&["create-admin", "ursula", "hunter2"])));
+    }
+
+    #[test]
+    fn unknown_commands_and_missing_arguments_are_rejected() {
+        assert_err!(Command::parse(args(&["frobnicate"])));
+        assert_err!(Command::parse(args(&["erase-subscriber-data"])));
+    }
+}
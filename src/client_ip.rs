@@ -0,0 +1,36 @@
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+};
+
+use actix_web::{web, FromRequest};
+
+/// Whether the `Forwarded` and `X-Forwarded-For` headers can be trusted.
+///
+/// Only enable this behind a reverse proxy that overwrites those headers,
+/// otherwise clients can pick whatever address they like.
+#[derive(Clone, Copy)]
+pub struct TrustProxyHeaders(pub bool);
+
+/// The address of the client that sent the request.
+pub struct ClientIp(pub String);
+
+impl FromRequest for ClientIp {
+    type Error = Infallible;
+
+    type Future = Ready<Result<ClientIp, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let trust_proxy_headers = req
+            .app_data::<web::Data<TrustProxyHeaders>>()
+            .map(|t| t.0)
+            .unwrap_or(false);
+        let connection_info = req.connection_info();
+        let ip = if trust_proxy_headers {
+            connection_info.realip_remote_addr()
+        } else {
+            connection_info.peer_addr()
+        };
+        ready(Ok(ClientIp(ip.unwrap_or("unknown").to_string())))
+    }
+}
@@ -1,7 +1,16 @@
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use std::num::NonZeroU32;
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use anyhow::Context;
 
+use crate::{
+    authentication::Role,
+    domain::{PasswordPolicy, SignupPolicy, SubscriberEmail},
+    email_client::EmailClient,
+};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String")]
 pub enum Environment {
     Development,
     Production
@@ -30,10 +39,25 @@ impl TryFrom<String> for Environment {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
+    /// Set from `APP_ENVIRONMENT` by `get_configuration`
+    pub environment: Environment,
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: String,
+    /// Namespace for the keys the application stores in Redis
+    pub redis_key_prefix: String,
+    pub subscriptions: SubscriptionSettings,
+    pub login_throttling: LoginThrottleSettings,
+    pub password_reset: PasswordResetSettings,
+    pub invitations: InvitationSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub sessions: SessionSettings,
+    pub idempotency: IdempotencySettings,
+    /// Single sign-on is disabled unless this is set
+    #[serde(default)]
+    pub oidc: Option<OidcSettings>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -42,6 +66,9 @@ pub struct ApplicationSettings {
     pub http_listen_port: u16,
     pub base_url: String,
     pub hmac_secret: String,
+    /// Take the client address from proxy headers, see `TrustProxyHeaders`
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -77,6 +104,10 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub authorization_token: String,
     pub timeout_milliseconds: u64,
+    /// Signs the calls the provider makes to `/webhooks/email-events`, which is only served
+    /// when it is set. Required in production.
+    #[serde(default)]
+    pub webhook_signing_secret: Option<String>,
 }
 
 impl EmailClientSettings {
@@ -100,6 +131,211 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub blocked_local_parts: Vec<String>,
+    #[serde(default)]
+    pub allowed_local_parts: Vec<String>,
+    /// Replaces the bundled list of disposable email domains
+    pub disposable_domains_path: Option<String>,
+    /// Forms submitted faster than this are assumed to come from bots, 0 disables the check
+    pub min_form_fill_seconds: u64,
+    pub ip_rate_limit: RateLimitSettings,
+    pub email_rate_limit: RateLimitSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    pub max_requests: u64,
+    pub window_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn signup_policy(&self) -> Result<SignupPolicy, anyhow::Error> {
+        let policy = SignupPolicy::new(
+            &self.blocked_domains,
+            &self.allowed_domains,
+            &self.blocked_local_parts,
+            &self.allowed_local_parts,
+        );
+        match &self.disposable_domains_path {
+            Some(path) => {
+                let list = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read disposable domains from {}", path))?;
+                Ok(policy.with_disposable_domains(&list))
+            }
+            None => Ok(policy),
+        }
+    }
+
+    pub fn min_form_fill_time(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.min_form_fill_seconds)
+    }
+}
+
+/// Failed login attempts are counted per username and per client address.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LoginThrottleSettings {
+    /// Failed attempts allowed before responses start being delayed
+    pub free_attempts: u64,
+    /// Delay after the first attempt over `free_attempts`, doubled by every further failure
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    /// How long failures are remembered since the last one
+    pub failure_window_seconds: u64,
+    pub username_lockout_threshold: u64,
+    pub ip_lockout_threshold: u64,
+    pub lockout_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordResetSettings {
+    /// How long a reset link stays valid
+    pub token_ttl_minutes: i64,
+    /// Reset emails requested from a single client address
+    pub rate_limit: RateLimitSettings,
+}
+
+impl PasswordResetSettings {
+    pub fn token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.token_ttl_minutes)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct InvitationSettings {
+    /// How long an invitation link stays valid
+    pub token_ttl_hours: i64,
+}
+
+impl InvitationSettings {
+    pub fn token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.token_ttl_hours)
+    }
+}
+
+/// How long an admin session lasts.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SessionSettings {
+    /// Log out sessions that have not been used for this long
+    pub idle_timeout_seconds: i64,
+    /// Log out sessions this long after logging in, however busy they are
+    pub absolute_timeout_seconds: i64,
+}
+
+impl SessionSettings {
+    pub fn idle_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.idle_timeout_seconds)
+    }
+
+    pub fn absolute_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.absolute_timeout_seconds)
+    }
+}
+
+/// How long saved responses are kept for retries.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct IdempotencySettings {
+    /// Completed keys older than this are forgotten, and can be used again for a new request
+    pub ttl_hours: i64,
+    /// How often the background worker deletes expired keys
+    pub cleanup_interval_seconds: u64,
+    /// Expired keys deleted per statement, to keep locks short
+    pub cleanup_batch_size: NonZeroU32,
+    /// Keys in progress for longer than this were abandoned, and can be claimed again
+    pub in_progress_timeout_seconds: i64,
+    /// How long a duplicate of a request in progress waits for its outcome, before being
+    /// turned away with `409 Conflict`
+    pub max_duplicate_wait_milliseconds: u64,
+    /// Sent in the `Retry-After` header of that `409 Conflict`
+    pub retry_after_seconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.ttl_hours)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+
+    pub fn in_progress_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.in_progress_timeout_seconds)
+    }
+
+    pub fn max_duplicate_wait(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.max_duplicate_wait_milliseconds)
+    }
+}
+
+/// An OpenID Connect provider admins can log in with.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct OidcSettings {
+    /// Where `/.well-known/openid-configuration` is found, and the expected `iss` claim
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Create an account for unknown users whose email the provider has verified
+    #[serde(default)]
+    pub auto_provision: bool,
+    /// The role of accounts created by `auto_provision`
+    #[serde(default = "default_oidc_role")]
+    pub default_role: Role,
+}
+
+fn default_oidc_role() -> Role {
+    Role::Viewer
+}
+
+/// What admin passwords have to look like, see `PasswordPolicy`.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize,
+    /// Bounds the cost of hashing a password
+    pub max_length: usize,
+    /// As estimated from the length and the kinds of characters used
+    pub min_entropy_bits: f64,
+    /// Replaces the bundled list of breached passwords
+    pub breached_passwords_path: Option<String>,
+}
+
+impl PasswordPolicySettings {
+    pub fn policy(&self) -> Result<PasswordPolicy, anyhow::Error> {
+        let policy = PasswordPolicy::new(self.min_length, self.max_length, self.min_entropy_bits);
+        match &self.breached_passwords_path {
+            Some(path) => {
+                let list = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read breached passwords from {}", path))?;
+                Ok(policy.with_breached_passwords(&list))
+            }
+            None => Ok(policy),
+        }
+    }
+}
+
+/// Argon2id parameters for new password hashes.
+///
+/// Hashes made with other parameters are upgraded when their user logs in.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordHashingSettings {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, anyhow::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))
+    }
+}
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir().expect("Failed to determine current directory");
     let config_dir = base_path.join("configuration");
@@ -122,7 +358,42 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .prefix_separator("_")
                 .separator("__")
         )
+        .set_override("environment", environment.as_str())?
         .build()?;
 
     settings.try_deserialize::<Settings>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencySettings;
+
+    fn idempotency_settings(cleanup_batch_size: &str) -> Result<IdempotencySettings, config::ConfigError> {
+        let yaml = format!(
+            "ttl_hours: 24\n\
+            cleanup_interval_seconds: 600\n\
+            cleanup_batch_size: {}\n\
+            in_progress_timeout_seconds: 300\n\
+            max_duplicate_wait_milliseconds: 0\n\
+            retry_after_seconds: 1\n",
+            cleanup_batch_size
+        );
+        config::Config::builder()
+            .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+            .build()?
+            .try_deserialize()
+    }
+
+    #[test]
+    fn a_positive_cleanup_batch_size_is_accepted() {
+        let settings = idempotency_settings("1000").unwrap();
+        assert_eq!(settings.cleanup_batch_size.get(), 1000);
+    }
+
+    #[test]
+    fn an_empty_or_negative_cleanup_batch_size_is_rejected() {
+        // The cleanup would never finish, or fail on every pass
+        assert!(idempotency_settings("0").is_err());
+        assert!(idempotency_settings("-1").is_err());
+    }
+}
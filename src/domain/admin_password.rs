@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+
+const BUNDLED_BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+/// Why a password was turned down, worded to be shown to the user.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PasswordPolicyViolation {
+    #[error("The password must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("The password must be at most {0} characters long")]
+    TooLong(usize),
+    #[error("The password cannot be the same as your username")]
+    SameAsUsername,
+    #[error("This password has appeared in a data breach, please choose another one")]
+    Breached,
+    #[error("This password is too easy to guess, try a longer passphrase or mixing in other kinds of characters")]
+    TooWeak,
+}
+
+/// What the password of an admin has to look like.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    min_entropy_bits: f64,
+    breached_passwords: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, max_length: usize, min_entropy_bits: f64) -> Self {
+        Self {
+            min_length,
+            max_length,
+            min_entropy_bits,
+            breached_passwords: parse_password_list(BUNDLED_BREACHED_PASSWORDS),
+        }
+    }
+
+    /// Replace the bundled list of breached passwords.
+    ///
+    /// The list contains one password per line, blank lines and lines starting with `#` are ignored.
+    pub fn with_breached_passwords(mut self, list: &str) -> Self {
+        self.breached_passwords = parse_password_list(list);
+        self
+    }
+}
+
+/// A password that complies with the `PasswordPolicy`.
+pub struct AdminPassword(String);
+
+impl AdminPassword {
+    pub fn parse(
+        password: String,
+        username: &str,
+        policy: &PasswordPolicy,
+    ) -> Result<Self, PasswordPolicyViolation> {
+        let length = password.chars().count();
+        if length < policy.min_length {
+            return Err(PasswordPolicyViolation::TooShort(policy.min_length));
+        }
+        // Long inputs make hashing expensive
+        if length > policy.max_length {
+            return Err(PasswordPolicyViolation::TooLong(policy.max_length));
+        }
+        let lowercase = password.to_lowercase();
+        if lowercase == username.trim().to_lowercase() {
+            return Err(PasswordPolicyViolation::SameAsUsername);
+        }
+        if policy.breached_passwords.contains(&lowercase) {
+            return Err(PasswordPolicyViolation::Breached);
+        }
+        if estimate_entropy_bits(&password) < policy.min_entropy_bits {
+            return Err(PasswordPolicyViolation::TooWeak);
+        }
+        Ok(Self(password))
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl AsRef<str> for AdminPassword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn parse_password_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// A rough estimate of how many guesses, in bits, a brute force attack needs.
+///
+/// Each character is worth as much as the size of the alphabet its kinds of
+/// characters come from. Characters repeating or continuing a sequence,
+/// e.g. `aaa` or `1234`, and characters seen several times are worth little.
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let mut alphabet = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        alphabet += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        alphabet += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        alphabet += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        alphabet += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        alphabet += 100;
+    }
+    let bits_per_char = f64::from(alphabet.max(1)).log2();
+
+    let mut seen: HashMap<char, u32> = HashMap::new();
+    let mut bits = 0.0;
+    for (i, c) in chars.iter().enumerate() {
+        let follows_pattern = i > 0 && {
+            let step = *c as i64 - chars[i - 1] as i64;
+            step.abs() <= 1
+        };
+        let times_seen = seen.entry(c.to_ascii_lowercase()).or_insert(0);
+        bits += if follows_pattern {
+            1.0
+        } else {
+            // Each reuse of a character is worth half as much as the previous one
+            bits_per_char / f64::from(1 << (*times_seen).min(8))
+        };
+        *times_seen += 1;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_entropy_bits, AdminPassword, PasswordPolicy, PasswordPolicyViolation};
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::new(12, 128, 50.0)
+    }
+
+    fn check(password: &str) -> Result<(), PasswordPolicyViolation> {
+        AdminPassword::parse(password.to_string(), "ursula", &policy()).map(|_| ())
+    }
+
+    #[test]
+    fn strong_passwords_are_accepted() {
+        assert_eq!(check("plum-ceiling-Harbour-42"), Ok(()));
+        assert_eq!(check(&uuid::Uuid::new_v4().to_string()), Ok(()));
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_eq!(check("a"), Err(PasswordPolicyViolation::TooShort(12)));
+        assert_eq!(check("x7#Kp2!qZ9a"), Err(PasswordPolicyViolation::TooShort(12)));
+    }
+
+    #[test]
+    fn long_passwords_are_rejected() {
+        let password = "x7#Kp2!qZ9a-".repeat(11);
+        assert_eq!(check(&password), Err(PasswordPolicyViolation::TooLong(128)));
+    }
+
+    #[test]
+    fn length_is_counted_in_characters_rather_than_bytes() {
+        assert_eq!(check("ěščřžýáíéúůĚ"), Ok(()));
+    }
+
+    #[test]
+    fn the_username_is_rejected_regardless_of_case() {
+        let policy = policy();
+        let result = AdminPassword::parse("Ursula-Smith-1".into(), "ursula-smith-1", &policy);
+        assert_eq!(result.err(), Some(PasswordPolicyViolation::SameAsUsername));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_regardless_of_case() {
+        assert_eq!(check("Password1234"), Err(PasswordPolicyViolation::Breached));
+        assert_eq!(check("correct horse battery staple"), Err(PasswordPolicyViolation::Breached));
+    }
+
+    #[test]
+    fn the_breached_list_can_be_replaced() {
+        let policy = policy().with_breached_passwords("# comment\n\nplum-ceiling-Harbour-42\n");
+        let result = AdminPassword::parse("plum-ceiling-harbour-42".into(), "ursula", &policy);
+        assert_eq!(result.err(), Some(PasswordPolicyViolation::Breached));
+        assert!(AdminPassword::parse("Password1234".into(), "ursula", &policy).is_ok());
+    }
+
+    #[test]
+    fn predictable_passwords_are_too_weak() {
+        assert_eq!(check("aaaaaaaaaaaaaaaa"), Err(PasswordPolicyViolation::TooWeak));
+        assert_eq!(check("abcdefghijklmnop"), Err(PasswordPolicyViolation::TooWeak));
+        assert_eq!(check("123412341234"), Err(PasswordPolicyViolation::TooWeak));
+        assert_eq!(check("abababababababab"), Err(PasswordPolicyViolation::TooWeak));
+    }
+
+    #[test]
+    fn more_kinds_of_characters_are_worth_more() {
+        assert!(estimate_entropy_bits("xKcw9#") > estimate_entropy_bits("xkcwqz"));
+    }
+}
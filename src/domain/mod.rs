@@ -1,7 +1,13 @@
 mod subscriber_name;
 mod subscriber_email;
 mod new_subscriber;
+mod signup_policy;
+mod admin_password;
+mod username;
 
 pub use subscriber_name::SubscriberName;
 pub use subscriber_email::SubscriberEmail;
 pub use new_subscriber::NewSubscriber;
+pub use signup_policy::SignupPolicy;
+pub use admin_password::{AdminPassword, PasswordPolicy, PasswordPolicyViolation};
+pub use username::Username;
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+
+use super::SubscriberEmail;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Which addresses are allowed to subscribe.
+///
+/// Allowlists take precedence over blocklists: an allowed domain skips every
+/// domain check, an allowed local part skips the role-address check.
+/// Domains match their subdomains as well.
+#[derive(Debug, Clone, Default)]
+pub struct SignupPolicy {
+    blocked_domains: HashSet<String>,
+    allowed_domains: HashSet<String>,
+    blocked_local_parts: HashSet<String>,
+    allowed_local_parts: HashSet<String>,
+    disposable_domains: HashSet<String>,
+}
+
+impl SignupPolicy {
+    pub fn new(
+        blocked_domains: &[String],
+        allowed_domains: &[String],
+        blocked_local_parts: &[String],
+        allowed_local_parts: &[String],
+    ) -> Self {
+        let normalise = |values: &[String]| values.iter().map(|v| v.trim().to_lowercase()).collect();
+        Self {
+            blocked_domains: normalise(blocked_domains),
+            allowed_domains: normalise(allowed_domains),
+            blocked_local_parts: normalise(blocked_local_parts),
+            allowed_local_parts: normalise(allowed_local_parts),
+            disposable_domains: parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS),
+        }
+    }
+
+    /// Replace the bundled list of disposable domains.
+    ///
+    /// The list contains one domain per line, blank lines and lines starting with `#` are ignored.
+    pub fn with_disposable_domains(mut self, list: &str) -> Self {
+        self.disposable_domains = parse_domain_list(list);
+        self
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let domain = email.domain().to_lowercase();
+        if !matches_domain(&self.allowed_domains, &domain) {
+            if matches_domain(&self.disposable_domains, &domain) {
+                return Err("Addresses from disposable email providers are not accepted".into());
+            }
+            if matches_domain(&self.blocked_domains, &domain) {
+                return Err(format!("Addresses at {} are not accepted", domain));
+            }
+        }
+
+        let local_part = email.local_part().to_lowercase();
+        // Sub-addressing does not turn a role address into a personal one
+        let local_part = local_part
+            .split_once('+')
+            .map(|(base, _tag)| base)
+            .unwrap_or(&local_part);
+        if !self.allowed_local_parts.contains(local_part)
+            && self.blocked_local_parts.contains(local_part)
+        {
+            return Err(format!(
+                "Role addresses such as {}@ are not accepted, please use a personal address",
+                local_part
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether `domain`, or any of its parent domains, is in `domains`.
+fn matches_domain(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{SignupPolicy, SubscriberEmail};
+    use claims::{assert_err, assert_ok};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn policy() -> SignupPolicy {
+        SignupPolicy::new(
+            &strings(&["spam.example"]),
+            &strings(&["trusted.example", "mailinator.com"]),
+            &strings(&["noreply", "abuse"]),
+            &strings(&[]),
+        )
+    }
+
+    #[test]
+    fn personal_addresses_are_accepted() {
+        assert_ok!(policy().check(&email("ursula@gmail.com")));
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        assert_err!(policy().check(&email("ursula@yopmail.com")));
+        assert_err!(policy().check(&email("ursula@eu.yopmail.com")));
+    }
+
+    #[test]
+    fn blocked_domains_are_rejected() {
+        assert_err!(policy().check(&email("ursula@spam.example")));
+        assert_err!(policy().check(&email("ursula@mail.spam.example")));
+    }
+
+    #[test]
+    fn allowed_domains_override_the_disposable_list() {
+        assert_ok!(policy().check(&email("ursula@mailinator.com")));
+    }
+
+    #[test]
+    fn role_addresses_are_rejected_regardless_of_case_and_tags() {
+        assert_err!(policy().check(&email("noreply@gmail.com")));
+        assert_err!(policy().check(&email("NoReply@gmail.com")));
+        assert_err!(policy().check(&email("abuse+newsletter@gmail.com")));
+    }
+
+    #[test]
+    fn allowed_local_parts_override_the_blocklist() {
+        let policy = SignupPolicy::new(&[], &[], &strings(&["admin"]), &strings(&["admin"]));
+        assert_ok!(policy.check(&email("admin@gmail.com")));
+    }
+
+    #[test]
+    fn the_disposable_list_can_be_replaced() {
+        let policy = policy().with_disposable_domains("# comment\n\nthrowaway.example\n");
+        assert_ok!(policy.check(&email("ursula@yopmail.com")));
+        assert_err!(policy.check(&email("ursula@throwaway.example")));
+    }
+}
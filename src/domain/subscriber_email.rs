@@ -4,13 +4,31 @@ use validator::ValidateEmail;
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// Parse and normalise an email address.
+    ///
+    /// Surrounding whitespace is trimmed and the domain is lowercased and converted
+    /// to its ASCII (punycode) form. The local part is kept as typed: it is
+    /// case-sensitive as far as the RFC is concerned, uniqueness is instead
+    /// enforced case-insensitively by the database.
     pub fn parse(s: String) -> Result<Self, String> {
-        if s.validate_email() {
-            Ok(Self(s))
+        let invalid = || format!("{} is not a valid subscriber email", s);
+        let (local_part, domain) = s.trim().rsplit_once('@').ok_or_else(invalid)?;
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let normalised = format!("{}@{}", local_part, domain);
+        if normalised.validate_email() {
+            Ok(Self(normalised))
         } else {
-            Err(format!("{} is not a valid subscriber email", s))
+            Err(invalid())
         }
     }
+
+    pub fn local_part(&self) -> &str {
+        self.0.rsplit_once('@').map(|(local_part, _)| local_part).unwrap_or_default()
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default()
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -27,7 +45,7 @@ impl std::fmt::Display for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     use fake::{faker::internet::en::SafeEmail, Fake};
     use crate::domain::SubscriberEmail;
     use rand::{rngs::StdRng, SeedableRng};
@@ -66,4 +84,22 @@ mod tests {
         let email = "@test.com".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = assert_ok!(SubscriberEmail::parse("  ursula@example.com\n".to_string()));
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn domain_is_lowercased_but_local_part_is_preserved() {
+        let email = assert_ok!(SubscriberEmail::parse("Ursula.LeGuin@Example.COM".to_string()));
+        assert_eq!(email.as_ref(), "Ursula.LeGuin@example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_converted_to_punycode() {
+        let email = assert_ok!(SubscriberEmail::parse("ursula@Bücher.example".to_string()));
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
 }
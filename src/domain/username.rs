@@ -0,0 +1,62 @@
+/// The name an admin logs in with, and is greeted by.
+#[derive(Debug)]
+pub struct Username(String);
+
+impl Username {
+    pub const MAX_LENGTH: usize = 64;
+
+    /// Letters, digits and `.`, `_`, `-`, `+`, `@`, so that usernames can be emails but
+    /// never markup.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Please choose a username".into());
+        }
+        if s.chars().count() > Self::MAX_LENGTH {
+            return Err(format!("The username must be at most {} characters long", Self::MAX_LENGTH));
+        }
+        let is_allowed = |c: char| c.is_ascii_alphanumeric() || ['.', '_', '-', '+', '@'].contains(&c);
+        if !s.chars().all(is_allowed) {
+            return Err("The username can only contain letters, digits and . _ - + @".into());
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl AsRef<str> for Username {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Username;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn usernames_and_emails_are_valid() {
+        assert_ok!(Username::parse("ursula"));
+        assert_ok!(Username::parse("le.guin-1929"));
+        assert_ok!(Username::parse("ursula+admin@example.com"));
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        assert_eq!(Username::parse("  ursula ").unwrap().as_ref(), "ursula");
+    }
+
+    #[test]
+    fn empty_or_too_long_usernames_are_rejected() {
+        assert_err!(Username::parse(" "));
+        assert_ok!(Username::parse(&"u".repeat(Username::MAX_LENGTH)));
+        assert_err!(Username::parse(&"u".repeat(Username::MAX_LENGTH + 1)));
+    }
+
+    #[test]
+    fn usernames_with_other_characters_are_rejected() {
+        for username in ["<script>", "ursula le guin", "ursula\"", "urs&ula", "ürsula"] {
+            assert_err!(Username::parse(username));
+        }
+    }
+}
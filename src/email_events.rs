@@ -0,0 +1,224 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{Executor, PgPool};
+
+/// What the email provider reports happened to an email it sent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmailEventKind {
+    Delivered,
+    SoftBounced,
+    HardBounced,
+    SpamComplaint,
+    Unsubscribed,
+}
+
+impl EmailEventKind {
+    /// The kind of a webhook `type`, e.g. `activity.hard_bounced`, `None` for untracked events.
+    pub fn from_webhook_type(event_type: &str) -> Option<Self> {
+        match event_type {
+            "activity.delivered" => Some(EmailEventKind::Delivered),
+            "activity.soft_bounced" => Some(EmailEventKind::SoftBounced),
+            "activity.hard_bounced" => Some(EmailEventKind::HardBounced),
+            "activity.spam_complaint" => Some(EmailEventKind::SpamComplaint),
+            "activity.unsubscribed" => Some(EmailEventKind::Unsubscribed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEventKind::Delivered => "delivered",
+            EmailEventKind::SoftBounced => "soft_bounced",
+            EmailEventKind::HardBounced => "hard_bounced",
+            EmailEventKind::SpamComplaint => "spam_complaint",
+            EmailEventKind::Unsubscribed => "unsubscribed",
+        }
+    }
+
+    /// The status subscriptions of the recipient move to, so that nothing is sent to them anymore.
+    ///
+    /// Soft bounces are temporary, the next issue is sent as usual.
+    fn subscription_status(&self) -> Option<&'static str> {
+        match self {
+            EmailEventKind::HardBounced => Some("bounced"),
+            EmailEventKind::SpamComplaint => Some("complained"),
+            EmailEventKind::Unsubscribed => Some("unsubscribed"),
+            EmailEventKind::Delivered | EmailEventKind::SoftBounced => None,
+        }
+    }
+}
+
+/// The parts of a webhook call we use, the payload is stored whole.
+#[derive(serde::Deserialize, Debug)]
+pub struct EmailWebhookPayload {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created_at: DateTime<Utc>,
+    pub data: EmailActivity,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct EmailActivity {
+    pub id: String,
+    pub email: ActivityEmail,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ActivityEmail {
+    pub recipient: ActivityRecipient,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ActivityRecipient {
+    pub email: String,
+}
+
+/// How far the signed timestamp of a webhook call may be from our clock.
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+/// Whether `signature`, the hex `Signature` header of a webhook call, is the HMAC-SHA256 of
+/// `{timestamp}.{body}` under the signing secret of the webhook, and `timestamp`, in seconds
+/// since the epoch, is within a few minutes of `now`.
+///
+/// The timestamp is signed so that a captured call cannot be replayed once it is stale.
+pub fn verify_webhook_signature(
+    signing_secret: &str,
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    let Ok(signed_at) = timestamp.trim().parse::<i64>() else {
+        return false;
+    };
+    if (now.timestamp() - signed_at).abs() > SIGNATURE_TOLERANCE_SECONDS {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(timestamp.trim().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Store an event, and stop sending to its recipient if they bounced, complained or unsubscribed.
+///
+/// `payload` is the body of the webhook call. Returns `false`, changing nothing, for an event
+/// that was already stored.
+#[tracing::instrument(name = "Record email event", skip(pool, event, payload), fields(event_id = %event.data.id))]
+pub async fn record_email_event(
+    pool: &PgPool,
+    kind: EmailEventKind,
+    event: &EmailWebhookPayload,
+    payload: &str,
+) -> Result<bool, anyhow::Error> {
+    let recipient = event.data.email.recipient.email.trim();
+    let mut transaction = pool.begin().await?;
+    let inserted = transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO email_events (provider_event_id, event_type, recipient, payload, occurred_at)
+            VALUES ($1, $2, $3, $4::text::jsonb, $5)
+            ON CONFLICT (provider_event_id) DO NOTHING
+            "#,
+            event.data.id,
+            kind.as_str(),
+            recipient,
+            payload,
+            event.created_at,
+        ))
+        .await
+        .context("Failed to store an email event")?
+        .rows_affected();
+    if inserted == 0 {
+        return Ok(false);
+    }
+    if let Some(status) = kind.subscription_status() {
+        transaction
+            .execute(sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                SET status = $1
+                WHERE lower(email) = lower($2)
+                    AND status IN ('pending_confirmation', 'confirmed')
+                "#,
+                status,
+                recipient,
+            ))
+            .await
+            .context("Failed to update the subscription of the recipient")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit an email event")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_webhook_signature, EmailEventKind};
+    use chrono::{DateTime, Duration, Utc};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    const BODY: &[u8] = br#"{"type": "activity.delivered"}"#;
+
+    fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn verify_at(now: DateTime<Utc>, timestamp: &str, signature: &str) -> bool {
+        verify_webhook_signature("secret", timestamp, BODY, signature, now)
+    }
+
+    #[test]
+    fn fresh_signatures_of_the_timestamp_and_body_are_accepted() {
+        let now = Utc::now();
+        let timestamp = now.timestamp().to_string();
+        assert!(verify_at(now, &timestamp, &sign("secret", &timestamp, BODY)));
+        let a_bit_later = now + Duration::minutes(4);
+        assert!(verify_at(a_bit_later, &timestamp, &sign("secret", &timestamp, BODY)));
+    }
+
+    #[test]
+    fn other_signatures_are_rejected() {
+        let now = Utc::now();
+        let timestamp = now.timestamp().to_string();
+        assert!(!verify_at(now, &timestamp, &sign("another secret", &timestamp, BODY)));
+        assert!(!verify_webhook_signature("secret", &timestamp, b"{}", &sign("secret", &timestamp, BODY), now));
+        assert!(!verify_at(now, &timestamp, "not hex"));
+        assert!(!verify_at(now, &timestamp, ""));
+    }
+
+    #[test]
+    fn stale_or_altered_timestamps_are_rejected() {
+        let now = Utc::now();
+        let stale = (now - Duration::minutes(6)).timestamp().to_string();
+        assert!(!verify_at(now, &stale, &sign("secret", &stale, BODY)));
+        let future = (now + Duration::minutes(6)).timestamp().to_string();
+        assert!(!verify_at(now, &future, &sign("secret", &future, BODY)));
+        let timestamp = now.timestamp().to_string();
+        let signature = sign("secret", &timestamp, BODY);
+        assert!(!verify_at(now, &(now.timestamp() - 1).to_string(), &signature));
+        assert!(!verify_at(now, "not a timestamp", &signature));
+    }
+
+    #[test]
+    fn only_tracked_event_types_are_recognised() {
+        assert_eq!(
+            EmailEventKind::from_webhook_type("activity.hard_bounced"),
+            Some(EmailEventKind::HardBounced)
+        );
+        assert_eq!(EmailEventKind::from_webhook_type("activity.opened"), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Forms older than this have to be reloaded before they can be submitted.
+const MAX_FORM_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum FormTimingError {
+    #[error("The form could not be verified, please reload the page and try again")]
+    Invalid,
+    #[error("The form has expired, please reload the page and try again")]
+    Expired,
+    #[error("The form was submitted too quickly, please wait a moment and try again")]
+    TooFast,
+}
+
+/// Signs the time a form was rendered, so that we can tell how long it took to fill it in.
+///
+/// Bots tend to submit forms as soon as they fetch them, if they fetch them at all.
+#[derive(Clone)]
+pub struct FormTimer {
+    secret: Vec<u8>,
+    min_fill_time: Duration,
+}
+
+impl FormTimer {
+    pub fn new(secret: &str, min_fill_time: Duration) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+            min_fill_time,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.min_fill_time.is_zero()
+    }
+
+    /// A token to embed in a form rendered now.
+    pub fn issue(&self) -> String {
+        let issued_at = Utc::now().timestamp_millis();
+        format!("{}.{}", issued_at, hex::encode(self.sign(issued_at)))
+    }
+
+    /// Check a token coming back with a submitted form.
+    pub fn check(&self, token: &str) -> Result<(), FormTimingError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let (issued_at, signature) = token.split_once('.').ok_or(FormTimingError::Invalid)?;
+        let issued_at: i64 = issued_at.parse().map_err(|_| FormTimingError::Invalid)?;
+        let signature = hex::decode(signature).map_err(|_| FormTimingError::Invalid)?;
+        self.mac(issued_at)
+            .verify_slice(&signature)
+            .map_err(|_| FormTimingError::Invalid)?;
+
+        let elapsed = Utc::now().timestamp_millis() - issued_at;
+        if elapsed < self.min_fill_time.as_millis() as i64 {
+            return Err(FormTimingError::TooFast);
+        }
+        if elapsed > MAX_FORM_AGE.as_millis() as i64 {
+            return Err(FormTimingError::Expired);
+        }
+        Ok(())
+    }
+
+    fn sign(&self, issued_at: i64) -> Vec<u8> {
+        self.mac(issued_at).finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self, issued_at: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC can take a key of any size");
+        mac.update(b"form-timing:");
+        mac.update(issued_at.to_string().as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_err, assert_ok};
+
+    use super::{FormTimer, FormTimingError};
+
+    #[test]
+    fn a_form_submitted_right_away_is_too_fast() {
+        let timer = FormTimer::new("secret", Duration::from_secs(60));
+        let token = timer.issue();
+        assert!(matches!(timer.check(&token), Err(FormTimingError::TooFast)));
+    }
+
+    #[test]
+    fn a_form_is_accepted_once_the_minimum_fill_time_has_passed() {
+        let timer = FormTimer::new("secret", Duration::from_millis(10));
+        let token = timer.issue();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_ok!(timer.check(&token));
+    }
+
+    #[test]
+    fn tampered_and_foreign_tokens_are_rejected() {
+        let timer = FormTimer::new("secret", Duration::from_millis(10));
+        let token = FormTimer::new("another secret", Duration::from_millis(10)).issue();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_err!(timer.check(&token));
+        assert_err!(timer.check("0.abcdef"));
+        assert_err!(timer.check("not a token"));
+    }
+
+    #[test]
+    fn the_check_is_skipped_when_disabled() {
+        let timer = FormTimer::new("secret", Duration::ZERO);
+        assert_ok!(timer.check(""));
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::num::NonZeroU32;
+
+use crate::configuration::{IdempotencySettings, Settings};
+use crate::startup::get_connection_pool;
+
+use super::persistence::{COMPLETED, IN_PROGRESS};
+
+pub async fn run_cleanup_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    cleanup_loop(connection_pool, configuration.idempotency).await
+}
+
+async fn cleanup_loop(pool: PgPool, settings: IdempotencySettings) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by the instrumentation, the next pass will try again
+        let _ = clean_up_idempotency_records(&pool, &settings).await;
+        tokio::time::sleep(settings.cleanup_interval()).await;
+    }
+}
+
+/// Delete expired keys, then report how big the table is.
+#[tracing::instrument(skip_all, err)]
+pub async fn clean_up_idempotency_records(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let deleted_rows = delete_expired_idempotency_records(
+        pool,
+        now - settings.ttl(),
+        now - settings.in_progress_timeout(),
+        settings.cleanup_batch_size,
+    )
+    .await?;
+    let size = idempotency_table_size(pool).await?;
+    tracing::info!(
+        idempotency.deleted_rows = deleted_rows,
+        idempotency.rows = size.rows,
+        idempotency.table_bytes = size.bytes,
+        "Idempotency table size"
+    );
+    Ok(())
+}
+
+/// Delete the completed keys created before `cutoff`, and the keys still in progress since
+/// before `in_progress_cutoff`, `batch_size` rows at a time.
+///
+/// Keys in progress are left alone until their request is considered abandoned, even past the
+/// TTL, as deleting them would let a duplicate run alongside the request. Claiming a key is
+/// committed on its own, so no row lock is held while a request is processed: every batch is
+/// its own statement to keep its locks short, and `SKIP LOCKED` only steps around rows that
+/// a concurrent claim is deleting at the same time.
+pub async fn delete_expired_idempotency_records(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    in_progress_cutoff: DateTime<Utc>,
+    batch_size: NonZeroU32,
+) -> Result<u64, anyhow::Error> {
+    let mut deleted_rows = 0;
+    loop {
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE ctid IN (
+                SELECT ctid
+                FROM idempotency
+                WHERE
+                    (state = $1 AND created_at < $2)
+                    OR (state = $3 AND created_at < $4)
+                LIMIT $5
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            COMPLETED,
+            cutoff,
+            IN_PROGRESS,
+            in_progress_cutoff,
+            i64::from(batch_size.get()),
+        )
+        .execute(pool)
+        .await
+        .context("Failed to delete expired idempotency keys")?
+        .rows_affected();
+        deleted_rows += deleted;
+        if deleted < u64::from(batch_size.get()) {
+            return Ok(deleted_rows);
+        }
+    }
+}
+
+pub struct IdempotencyTableSize {
+    pub rows: i64,
+    /// On disk, including indexes and the out-of-line storage of response bodies
+    pub bytes: i64,
+}
+
+pub async fn idempotency_table_size(pool: &PgPool) -> Result<IdempotencyTableSize, anyhow::Error> {
+    sqlx::query_as!(
+        IdempotencyTableSize,
+        r#"
+        SELECT
+            (SELECT count(*) FROM idempotency) AS "rows!",
+            pg_total_relation_size('idempotency') AS "bytes!"
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to measure the idempotency table")
+}
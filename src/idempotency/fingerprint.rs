@@ -0,0 +1,79 @@
+use sha2::{Digest, Sha256};
+
+/// Form fields that travel with every submission without being part of what it asks for.
+const IGNORED_FORM_FIELDS: [&str; 2] = ["csrf_token", "idempotency_key"];
+
+/// A digest of what a request asks for, to tell a retry from another request reusing its key.
+///
+/// The body is normalised first, so that a retry encoding the same payload differently still
+/// matches: JSON objects are compared whatever the order of their keys, and form fields whatever
+/// their order, leaving out the CSRF token and the idempotency key.
+pub fn request_fingerprint(method: &str, path: &str, content_type: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(normalise_body(content_type, body));
+    hex::encode(hasher.finalize())
+}
+
+fn normalise_body(content_type: &str, body: &[u8]) -> Vec<u8> {
+    match content_type {
+        "application/json" => match serde_json::from_slice::<serde_json::Value>(body) {
+            // Objects are maps sorted by key
+            Ok(value) => value.to_string().into_bytes(),
+            Err(_) => body.to_vec(),
+        },
+        "application/x-www-form-urlencoded" => {
+            match serde_urlencoded::from_bytes::<Vec<(String, String)>>(body) {
+                Ok(mut fields) => {
+                    fields.retain(|(name, _)| !IGNORED_FORM_FIELDS.contains(&name.as_str()));
+                    fields.sort();
+                    serde_urlencoded::to_string(fields)
+                        .map(String::into_bytes)
+                        .unwrap_or_else(|_| body.to_vec())
+                }
+                Err(_) => body.to_vec(),
+            }
+        }
+        _ => body.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::request_fingerprint;
+
+    const FORM: &str = "application/x-www-form-urlencoded";
+    const JSON: &str = "application/json";
+
+    #[test]
+    fn form_fields_are_compared_whatever_their_order() {
+        let first = request_fingerprint("POST", "/admin/newsletters", FORM, b"title=A&content_text=B");
+        let second = request_fingerprint("POST", "/admin/newsletters", FORM, b"content_text=B&title=A");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn csrf_tokens_and_idempotency_keys_are_left_out() {
+        let first = request_fingerprint("POST", "/admin/newsletters", FORM, b"title=A&csrf_token=x&idempotency_key=1");
+        let second = request_fingerprint("POST", "/admin/newsletters", FORM, b"idempotency_key=2&title=A&csrf_token=y");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn json_objects_are_compared_whatever_the_order_of_their_keys() {
+        let first = request_fingerprint("POST", "/api/v1/newsletters", JSON, br#"{"title": "A", "content": {"text": "B", "html": "C"}}"#);
+        let second = request_fingerprint("POST", "/api/v1/newsletters", JSON, br#"{"content":{"html":"C","text":"B"},"title":"A"}"#);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_payloads_or_routes_do_not_match() {
+        let fingerprint = request_fingerprint("POST", "/api/v1/newsletters", JSON, br#"{"title": "A"}"#);
+        assert_ne!(fingerprint, request_fingerprint("POST", "/api/v1/newsletters", JSON, br#"{"title": "B"}"#));
+        assert_ne!(fingerprint, request_fingerprint("POST", "/api/v1/other", JSON, br#"{"title": "A"}"#));
+        assert_ne!(fingerprint, request_fingerprint("PUT", "/api/v1/newsletters", JSON, br#"{"title": "A"}"#));
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    cell::RefCell,
+    future::{ready, Ready},
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderValue, RETRY_AFTER},
+        StatusCode,
+    },
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use futures_util::future::LocalBoxFuture;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    authentication::{ApiCaller, UserId},
+    configuration::IdempotencySettings,
+    utils::peek_body,
+};
+
+use super::{
+    release_key, request_fingerprint, save_response, try_processing, IdempotencyKey, IdempotencyScope,
+    NextAction,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+#[derive(serde::Deserialize)]
+struct IdempotencyFormData {
+    idempotency_key: Option<String>,
+}
+
+/// Make the mutating requests of a route idempotent.
+///
+/// The key is taken from the `Idempotency-Key` header, or else from the `idempotency_key`
+/// field of a form. Keys are scoped to the logged in user, or to the API token the request
+/// was made with. Retries get the saved response back instead of being processed again, while
+/// a key reused for a different request is rejected with `422 Unprocessable Entity`. A
+/// duplicate of a request still in progress is rejected with `409 Conflict` and `Retry-After`,
+/// unless it gets its outcome within [`IdempotencySettings::max_duplicate_wait`]. Keys expire
+/// after [`IdempotencySettings::ttl`].
+///
+/// Handlers make their changes through a [`RequestTransaction`], which is committed along
+/// with the saved response, or rolled back if the request fails. Requests without a key are
+/// processed as they come, unless the key is required with [`Idempotent::require_key`].
+#[derive(Clone, Copy, Default)]
+pub struct Idempotent {
+    on_replay: Option<fn()>,
+    key_required: bool,
+}
+
+impl Idempotent {
+    /// Call `f` when a saved response is sent back, e.g. to repeat a flash message.
+    pub fn on_replay(mut self, f: fn()) -> Self {
+        self.on_replay = Some(f);
+        self
+    }
+
+    /// Reject mutating requests without a key with `400 Bad Request`.
+    pub fn require_key(mut self) -> Self {
+        self.key_required = true;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotent
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = IdempotentMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotentMiddleware {
+            service: Rc::new(service),
+            options: *self,
+        }))
+    }
+}
+
+pub struct IdempotentMiddleware<S> {
+    service: Rc<S>,
+    options: Idempotent,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotentMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let options = self.options;
+        Box::pin(async move {
+            if req.method().is_safe() {
+                return service.call(req).await.map(ServiceResponse::map_into_boxed_body);
+            }
+            process_once(req, service, options).await
+        })
+    }
+}
+
+async fn process_once<S, B>(
+    mut req: ServiceRequest,
+    service: Rc<S>,
+    options: Idempotent,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let scope = request_scope(&req)?;
+    let body = peek_body(&mut req).await?;
+    let idempotency_key = match read_idempotency_key(&req, &body) {
+        Ok(None) if options.key_required => {
+            let response = error_response(&req, StatusCode::BAD_REQUEST, "Missing idempotency key");
+            return Ok(req.into_response(response));
+        }
+        Ok(key) => key,
+        Err(e) => {
+            let response = error_response(&req, StatusCode::BAD_REQUEST, &e.to_string());
+            return Ok(req.into_response(response));
+        }
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .cloned()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing database pool"))?;
+    let settings = req
+        .app_data::<web::Data<IdempotencySettings>>()
+        .cloned()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing idempotency settings"))?;
+
+    let transaction = match &idempotency_key {
+        Some(key) => match try_processing(&pool, key, &scope, &request_hash(&req, &body), &settings)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?
+        {
+            NextAction::StartProcessing(transaction) => *transaction,
+            NextAction::ReturnSavedResponse(response) => {
+                if let Some(on_replay) = options.on_replay {
+                    on_replay();
+                }
+                return Ok(req.into_response(response));
+            }
+            NextAction::RejectPayloadMismatch => {
+                let response = error_response(
+                    &req,
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Idempotency key reused with different payload",
+                );
+                return Ok(req.into_response(response));
+            }
+            NextAction::RejectInProgress => {
+                let mut response = error_response(
+                    &req,
+                    StatusCode::CONFLICT,
+                    "A request with this idempotency key is still being processed",
+                );
+                response.headers_mut().insert(
+                    RETRY_AFTER,
+                    HeaderValue::from(settings.retry_after_seconds),
+                );
+                return Ok(req.into_response(response));
+            }
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a connection from the pool")
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    };
+    let slot = TransactionSlot(Rc::new(RefCell::new(Some(transaction))));
+    req.extensions_mut().insert(slot.clone());
+
+    let outcome = service.call(req).await;
+    let transaction = slot.0.take();
+    let response = match outcome {
+        Ok(response) if !is_failure(response.status()) => response,
+        outcome => {
+            // Dropping the transaction rolls it back
+            drop(transaction);
+            release(&pool, idempotency_key.as_ref(), &scope).await;
+            return outcome.map(ServiceResponse::map_into_boxed_body);
+        }
+    };
+    let transaction = transaction.ok_or_else(|| {
+        actix_web::error::ErrorInternalServerError("The request transaction was not handed back")
+    })?;
+    match idempotency_key {
+        Some(key) => {
+            let (request, response) = response.into_parts();
+            match save_response(transaction, &key, &scope, response.map_into_boxed_body()).await {
+                Ok(response) => Ok(ServiceResponse::new(request, response)),
+                Err(e) => {
+                    release(&pool, Some(&key), &scope).await;
+                    Err(actix_web::error::ErrorInternalServerError(e))
+                }
+            }
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the request transaction")
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok(response.map_into_boxed_body())
+        }
+    }
+}
+
+fn is_failure(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+/// Give up the key of a failed request, so that a retry is processed afresh.
+async fn release(pool: &PgPool, idempotency_key: Option<&IdempotencyKey>, scope: &IdempotencyScope) {
+    let Some(idempotency_key) = idempotency_key else {
+        return;
+    };
+    if let Err(e) = release_key(pool, idempotency_key, scope).await {
+        // The key is freed once its request is considered abandoned
+        tracing::warn!(error.cause_chain = ?e, error.message = %e, "Failed to release an idempotency key");
+    }
+}
+
+/// Idempotent routes are authenticated, either with an API token or with a session.
+fn request_scope(req: &ServiceRequest) -> Result<IdempotencyScope, actix_web::Error> {
+    let extensions = req.extensions();
+    if let Some(caller) = extensions.get::<ApiCaller>() {
+        return Ok(IdempotencyScope::ApiToken {
+            user_id: caller.user_id,
+            token_id: caller.token_id,
+        });
+    }
+    extensions
+        .get::<UserId>()
+        .map(|user_id| IdempotencyScope::User(**user_id))
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Idempotent routes must be authenticated"))
+}
+
+/// The key sent with the request, if any, or why it is unusable.
+fn read_idempotency_key(
+    req: &ServiceRequest,
+    body: &[u8],
+) -> Result<Option<IdempotencyKey>, anyhow::Error> {
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| anyhow::anyhow!("Invalid {} header", IDEMPOTENCY_KEY_HEADER))?
+                .to_string(),
+        ),
+        None if req.content_type() == "application/x-www-form-urlencoded" => {
+            serde_urlencoded::from_bytes::<IdempotencyFormData>(body)
+                .ok()
+                .and_then(|f| f.idempotency_key)
+        }
+        None => None,
+    };
+    key.map(IdempotencyKey::try_from).transpose()
+}
+
+fn request_hash(req: &ServiceRequest, body: &[u8]) -> String {
+    request_fingerprint(req.method().as_str(), req.path(), req.content_type(), body)
+}
+
+/// Clients sending JSON get the error in JSON, like the rest of the API errors.
+fn error_response(req: &ServiceRequest, status: StatusCode, message: &str) -> HttpResponse {
+    if req.content_type() == "application/json" {
+        HttpResponse::build(status).json(serde_json::json!({ "error": message }))
+    } else {
+        HttpResponse::build(status).body(message.to_string())
+    }
+}
+
+/// Where the transaction of a request waits while the handler is not using it.
+#[derive(Clone)]
+struct TransactionSlot(Rc<RefCell<Option<Transaction<'static, Postgres>>>>);
+
+/// The transaction of a request to a route wrapped in [`Idempotent`].
+///
+/// The middleware commits it once the handler has responded, so handlers never commit it
+/// themselves.
+pub struct RequestTransaction {
+    transaction: Option<Transaction<'static, Postgres>>,
+    slot: TransactionSlot,
+}
+
+impl FromRequest for RequestTransaction {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let slot = req.extensions().get::<TransactionSlot>().cloned();
+        let transaction = slot.map(|slot| (slot.0.take(), slot));
+        ready(match transaction {
+            Some((Some(transaction), slot)) => Ok(Self {
+                transaction: Some(transaction),
+                slot,
+            }),
+            _ => Err(actix_web::error::ErrorInternalServerError(
+                "The route is not wrapped in the Idempotent middleware",
+            )),
+        })
+    }
+}
+
+impl Deref for RequestTransaction {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for RequestTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transaction.as_mut().unwrap()
+    }
+}
+
+impl Drop for RequestTransaction {
+    /// Hand the transaction back to the middleware.
+    fn drop(&mut self) {
+        if let Some(transaction) = self.transaction.take() {
+            self.slot.0.replace(Some(transaction));
+        }
+    }
+}
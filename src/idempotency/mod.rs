@@ -1,7 +1,16 @@
+mod cleanup;
+mod fingerprint;
 mod key;
+mod middleware;
 mod persistence;
 
+pub use cleanup::{
+    clean_up_idempotency_records, delete_expired_idempotency_records, idempotency_table_size,
+    run_cleanup_worker_until_stopped, IdempotencyTableSize,
+};
+pub use fingerprint::request_fingerprint;
 pub use key::IdempotencyKey;
+pub use middleware::{Idempotent, RequestTransaction, IDEMPOTENCY_KEY_HEADER};
 pub use persistence::get_saved_response;
 pub use persistence::save_response;
-pub use persistence::{NextAction, try_processing};
+pub use persistence::{release_key, IdempotencyScope, NextAction, try_processing};
@@ -1,10 +1,43 @@
 use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use chrono::Utc;
+use std::time::Instant;
 use sqlx::Executor;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::configuration::IdempotencySettings;
+
 use super::key::IdempotencyKey;
 
+/// States of a key, in the `state` column
+pub(super) const IN_PROGRESS: &str = "in_progress";
+pub(super) const COMPLETED: &str = "completed";
+
+/// Who a key belongs to: the same key sent by someone else is a different request.
+#[derive(Copy, Clone, Debug)]
+pub enum IdempotencyScope {
+    /// A user logged in with a session
+    User(Uuid),
+    /// A request authenticated with an API token, on behalf of its owner
+    ApiToken { user_id: Uuid, token_id: Uuid },
+}
+
+impl IdempotencyScope {
+    fn user_id(&self) -> Uuid {
+        match self {
+            IdempotencyScope::User(user_id) => *user_id,
+            IdempotencyScope::ApiToken { user_id, .. } => *user_id,
+        }
+    }
+
+    fn api_token_id(&self) -> Option<Uuid> {
+        match self {
+            IdempotencyScope::User(_) => None,
+            IdempotencyScope::ApiToken { token_id, .. } => Some(*token_id),
+        }
+    }
+}
+
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name = "header_pair")]
 struct HeaderPairRecord {
@@ -15,7 +48,7 @@ struct HeaderPairRecord {
 pub async fn get_saved_response(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
@@ -26,9 +59,11 @@ pub async fn get_saved_response(
         FROM idempotency
         WHERE
             user_id = $1
-            AND idempotency_key = $2
+            AND api_token_id IS NOT DISTINCT FROM $2
+            AND idempotency_key = $3
         "#,
-        user_id,
+        scope.user_id(),
+        scope.api_token_id(),
         idempotency_key.as_ref(),
     )
     .fetch_optional(pool)
@@ -48,7 +83,7 @@ pub async fn get_saved_response(
 pub async fn save_response(
     mut transaction: Transaction<'static, Postgres>,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
     http_response: HttpResponse,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_response.into_parts();
@@ -70,17 +105,21 @@ pub async fn save_response(
             r#"
             UPDATE idempotency
             SET
-                response_status_code = $1,
-                response_headers = $2,
-                response_body = $3
+                state = $1,
+                response_status_code = $2,
+                response_headers = $3,
+                response_body = $4
             WHERE
-                user_id = $4
-                AND idempotency_key = $5
+                user_id = $5
+                AND api_token_id IS NOT DISTINCT FROM $6
+                AND idempotency_key = $7
             "#,
+            COMPLETED,
             status_code,
             headers,
             body.as_ref(),
-            user_id,
+            scope.user_id(),
+            scope.api_token_id(),
             idempotency_key.as_ref(),
         ))
         .await?;
@@ -91,32 +130,147 @@ pub async fn save_response(
 }
 
 pub enum NextAction {
-    StartProcessing(Transaction<'static, Postgres>),
+    StartProcessing(Box<Transaction<'static, Postgres>>),
     ReturnSavedResponse(HttpResponse),
+    /// The key was first used with another request
+    RejectPayloadMismatch,
+    /// Another request with the key is still being processed
+    RejectInProgress,
 }
 
+/// How often a duplicate request looks again at a key in progress, while it waits.
+const IN_PROGRESS_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Claim the key for this request, unless a request carrying it was processed already.
+///
+/// Claims are committed straight away, marking the key in progress, so that a concurrent
+/// duplicate finds out without waiting on a lock: it is turned away, or waits for the outcome
+/// for as long as [`IdempotencySettings::max_duplicate_wait`] allows. `request_hash` is the
+/// fingerprint of the request, which retries must share.
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
+    request_hash: &str,
+    settings: &IdempotencySettings,
 ) -> Result<NextAction, anyhow::Error> {
-    let mut transaction = pool.begin().await?;
-    let query = sqlx::query!(
+    let wait_until = Instant::now() + settings.max_duplicate_wait();
+    loop {
+        if claim_key(pool, idempotency_key, scope, request_hash, settings).await? {
+            let transaction = pool.begin().await?;
+            return Ok(NextAction::StartProcessing(Box::new(transaction)));
+        }
+        let saved = sqlx::query!(
+            r#"
+            SELECT request_hash, state
+            FROM idempotency
+            WHERE
+                user_id = $1
+                AND api_token_id IS NOT DISTINCT FROM $2
+                AND idempotency_key = $3
+            "#,
+            scope.user_id(),
+            scope.api_token_id(),
+            idempotency_key.as_ref(),
+        )
+        .fetch_optional(pool)
+        .await?;
+        let Some(saved) = saved else {
+            // The request holding the key failed and released it in the meantime
+            continue;
+        };
+        // Keys saved before requests were fingerprinted match any request
+        if saved.request_hash.is_some_and(|saved_hash| saved_hash != request_hash) {
+            return Ok(NextAction::RejectPayloadMismatch);
+        }
+        if saved.state == COMPLETED {
+            let saved_response = get_saved_response(pool, idempotency_key, scope)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("We expected a saved response but we didn't find it"))?;
+            return Ok(NextAction::ReturnSavedResponse(saved_response));
+        }
+        if Instant::now() + IN_PROGRESS_POLL_INTERVAL > wait_until {
+            return Ok(NextAction::RejectInProgress);
+        }
+        tokio::time::sleep(IN_PROGRESS_POLL_INTERVAL).await;
+    }
+}
+
+/// Mark the key in progress for this request, returning whether it was free to take.
+///
+/// Expired keys are free again, and so are keys whose request has been in progress for longer
+/// than [`IdempotencySettings::in_progress_timeout`], e.g. because the server stopped midway.
+/// Keys in progress do not expire before that, whatever the TTL, so that a request still
+/// running never loses its key.
+async fn claim_key(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+    request_hash: &str,
+    settings: &IdempotencySettings,
+) -> Result<bool, anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE
+            user_id = $1
+            AND api_token_id IS NOT DISTINCT FROM $2
+            AND idempotency_key = $3
+            AND (
+                (state = $4 AND created_at < $5)
+                OR (state = $6 AND created_at < $7)
+            )
+        "#,
+        scope.user_id(),
+        scope.api_token_id(),
+        idempotency_key.as_ref(),
+        COMPLETED,
+        now - settings.ttl(),
+        IN_PROGRESS,
+        now - settings.in_progress_timeout(),
+    )
+    .execute(pool)
+    .await?;
+    let n_inserted_rows = sqlx::query!(
         r#"
-        INSERT INTO idempotency (user_id, idempotency_key, created_at)
-        VALUES ($1, $2, now())
+        INSERT INTO idempotency (user_id, api_token_id, idempotency_key, request_hash, state, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
         ON CONFLICT DO NOTHING
         "#,
-        user_id,
+        scope.user_id(),
+        scope.api_token_id(),
         idempotency_key.as_ref(),
-    );
-    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
-    if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(transaction))
-    } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response but we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
-    }
+        request_hash,
+        IN_PROGRESS,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_inserted_rows > 0)
+}
+
+/// Give up the key of a request that failed, so that a retry is processed afresh.
+pub async fn release_key(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE
+            user_id = $1
+            AND api_token_id IS NOT DISTINCT FROM $2
+            AND idempotency_key = $3
+            AND state = $4
+        "#,
+        scope.user_id(),
+        scope.api_token_id(),
+        idempotency_key.as_ref(),
+        IN_PROGRESS,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
 }
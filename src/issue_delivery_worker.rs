@@ -40,6 +40,23 @@ pub enum ExecutionOutcome {
     EmptyQueue,
 }
 
+#[derive(Clone, Copy, Debug)]
+enum DeliveryOutcome {
+    Delivered,
+    Failed,
+    InvalidEmail,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::InvalidEmail => "invalid_email",
+        }
+    }
+}
+
 #[tracing::instrument(
     skip_all,
     fields(
@@ -57,7 +74,7 @@ pub async fn try_execute_task(pool: &PgPool, email_client: &EmailClient) -> Resu
     Span::current()
         .record("newsletter_issue_id", display(issue_id))
         .record("subscriber_email", display(&email));
-    match SubscriberEmail::parse(email.clone()) {
+    let outcome = match SubscriberEmail::parse(email.clone()) {
         Ok(email) => {
             let issue = get_issue(pool, issue_id).await?;
             if let Err(e) = email_client
@@ -74,6 +91,9 @@ pub async fn try_execute_task(pool: &PgPool, email_client: &EmailClient) -> Resu
                     error.message = %e,
                     "Failed to deliver issue to a confirmed subscriber. Skipping.",
                 );
+                DeliveryOutcome::Failed
+            } else {
+                DeliveryOutcome::Delivered
             }
         }
         Err(e) => {
@@ -82,9 +102,10 @@ pub async fn try_execute_task(pool: &PgPool, email_client: &EmailClient) -> Resu
                 error.message = %e,
                 "Skipping a confirmed subscriber. Their stored email is invalid.",
             );
+            DeliveryOutcome::InvalidEmail
         }
-    }
-    delete_task(transaction, issue_id, &email).await?;
+    };
+    delete_task(transaction, issue_id, &email, outcome).await?;
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
@@ -122,7 +143,23 @@ async fn delete_task(
     mut transaction: PgTransaction,
     issue_id: Uuid,
     email: &str,
+    outcome: DeliveryOutcome,
 ) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_history (
+            newsletter_issue_id,
+            subscriber_email,
+            outcome,
+            attempted_at
+        )
+        VALUES ($1, $2, $3, now())
+        "#,
+        issue_id,
+        email,
+        outcome.as_str(),
+    );
+    transaction.execute(query).await?;
     let query = sqlx::query!(
         r#"
         DELETE FROM issue_delivery_queue
@@ -9,3 +9,11 @@ pub mod session_state;
 pub mod utils;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod subscriber_data;
+pub mod cli;
+pub mod rate_limit;
+pub mod client_ip;
+pub mod form_timing;
+pub mod session_registry;
+pub mod audit;
+pub mod email_events;
@@ -1,13 +1,24 @@
 use std::fmt::{Debug, Display};
 
 use tokio::task::JoinError;
+use zero2prod::cli::{run_command, Command};
 use zero2prod::configuration::get_configuration;
+use zero2prod::idempotency::run_cleanup_worker_until_stopped;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::Application;
 use zero2prod::telemetry;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let command = Command::parse(std::env::args().skip(1))?;
+    if command != Command::Serve {
+        // Keep stdout clean for the command's output
+        let subscriber = telemetry::get_subscriber("info".to_string(), std::io::stderr);
+        telemetry::init_subscriber(subscriber);
+        let configuration = get_configuration().expect("Failed to read configuration.");
+        return run_command(command, configuration).await;
+    }
+
     let subscriber = telemetry::get_subscriber("info".to_string(), std::io::stdout);
     telemetry::init_subscriber(subscriber);
 
@@ -15,11 +26,13 @@ async fn main() -> anyhow::Result<()> {
 
     let application = Application::build(configuration.clone()).await?;
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let cleanup_task = tokio::spawn(run_cleanup_worker_until_stopped(configuration));
 
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o),
+        o = cleanup_task => report_exit("Idempotency cleanup worker", o),
     };
 
     Ok(())
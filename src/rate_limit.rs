@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+
+use crate::configuration::RateLimitSettings;
+
+pub enum RateLimitOutcome {
+    Allowed,
+    Exceeded { retry_after: Duration },
+}
+
+/// Fixed-window request counters kept in Redis.
+///
+/// Every key gets its own window, which starts with the first request and lasts
+/// `window_seconds`. Counters are shared by all the instances of the application.
+#[derive(Clone)]
+pub struct RateLimiter {
+    connection: ConnectionManager,
+    key_prefix: String,
+}
+
+impl RateLimiter {
+    pub fn new(connection: ConnectionManager, key_prefix: String) -> Self {
+        Self {
+            connection,
+            key_prefix,
+        }
+    }
+
+    /// Count a request against `key`, and check whether it is over `limit`.
+    #[tracing::instrument(name = "Check rate limit", skip(self, limit))]
+    pub async fn hit(
+        &self,
+        key: &str,
+        limit: &RateLimitSettings,
+    ) -> Result<RateLimitOutcome, anyhow::Error> {
+        let key = format!("{}:rate_limit:{}", self.key_prefix, key);
+        let mut connection = self.connection.clone();
+        let (count, ttl): (u64, i64) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(limit.window_seconds)
+            .arg("NX")
+            .ignore()
+            .incr(&key, 1)
+            .ttl(&key)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to update the rate limit counter")?;
+        if count > limit.max_requests {
+            // A negative TTL means the key has just expired, the window is over
+            let retry_after = Duration::from_secs(ttl.max(1) as u64);
+            Ok(RateLimitOutcome::Exceeded { retry_after })
+        } else {
+            Ok(RateLimitOutcome::Allowed)
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, UserId},
+    client_ip::ClientIp,
+    domain::SubscriberEmail,
+    session_state::TypedSession,
+    utils::{escape_html, see_other},
+};
+
+pub async fn get_account_email(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = sqlx::query!(
+        "SELECT email FROM users WHERE user_id = $1",
+        *user_id.into_inner(),
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to fetch the email of the user")
+    .map_err(actix_web::error::ErrorInternalServerError)?
+    .email;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("account_email.html"),
+            msg_html,
+            csrf_token(&session)?,
+            escape_html(email.as_deref().unwrap_or_default())
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AccountEmailFormData {
+    email: String,
+}
+
+pub async fn post_account_email(
+    form: web::Form<AccountEmailFormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/email"));
+        }
+    };
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        -- Whoever types in an address has not shown that they receive mail there
+        SET email = $1, email_verified = email_verified AND lower(email) = lower($1)
+        WHERE user_id = $2
+        "#,
+        email.as_ref(),
+        user_id,
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(_) => {
+            record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::EmailChanged, &client_ip.0))
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            FlashMessage::info("Your email address has been updated").send()
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error("This email address is used by another account").send()
+        }
+        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+    }
+    Ok(see_other("/admin/email"))
+}
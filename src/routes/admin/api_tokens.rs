@@ -0,0 +1,148 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        create_api_token, csrf_token, list_api_tokens, revoke_api_token, ApiScope, Role, UserId,
+    },
+    client_ip::ClientIp,
+    session_state::TypedSession,
+    utils::{escape_html, see_other},
+};
+
+pub async fn get_api_tokens(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token(&session)?;
+    let tokens = list_api_tokens(&pool, *user_id.into_inner())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut tokens_html = String::new();
+    for t in tokens {
+        writeln!(
+            tokens_html,
+            r#"<tr><td>{name}</td><td>{scopes}</td><td>{created_at}</td><td>{last_used_at}</td><td>
+                <form action="/admin/api_tokens/{id}/revoke" method="post"><input type="hidden" name="csrf_token" value="{csrf_token}" /><button type="submit">Revoke</button></form>
+            </td></tr>"#,
+            name = escape_html(&t.name),
+            scopes = escape_html(&t.scopes.join(", ")),
+            created_at = t.created_at.format("%Y-%m-%d %H:%M"),
+            last_used_at = t
+                .last_used_at
+                .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".to_string()),
+            id = t.token_id,
+        )
+        .unwrap();
+    }
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        writeln!(
+            scopes_html,
+            r#"<label><input type="checkbox" name="scope" value="{scope}" /> {scope} (needs the {} role)</label>"#,
+            scope.required_role(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("api_tokens.html"),
+            msg_html, tokens_html, csrf_token, scopes_html
+        )))
+}
+
+#[tracing::instrument(name = "Create an API token", skip(form, client_ip, pool))]
+pub async fn post_create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // A plain struct cannot hold the repeated `scope` checkboxes
+    let mut name = String::new();
+    let mut scopes = Vec::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "name" => name = value.trim().to_string(),
+            "scope" => match ApiScope::try_from(value) {
+                Ok(scope) if !scopes.contains(&scope) => scopes.push(scope),
+                Ok(_) => {}
+                Err(e) => {
+                    FlashMessage::error(e).send();
+                    return Ok(see_other("/admin/api_tokens"));
+                }
+            },
+            _ => {}
+        }
+    }
+    if name.is_empty() {
+        FlashMessage::error("Please give the token a name").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("Please pick at least one scope").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if let Some(scope) = scopes.iter().find(|s| !role.allows(s.required_role())) {
+        FlashMessage::error(format!(
+            "You need the {} role to use the {} scope",
+            scope.required_role(),
+            scope
+        ))
+        .send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+
+    let user_id = *user_id.into_inner();
+    let (token_id, token) = create_api_token(&pool, user_id, &name, &scopes)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        pool.get_ref(),
+        AuditEvent::new(user_id, AuditAction::ApiTokenCreated, &client_ip.0).target("api_token", token_id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("api_token_created.html"), token)))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(client_ip, pool))]
+pub async fn post_revoke_api_token(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (user_id, id) = (*user_id.into_inner(), id.into_inner());
+    if !revoke_api_token(&pool, user_id, id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("API token not found"));
+    }
+    record_audit_event(
+        pool.get_ref(),
+        AuditEvent::new(user_id, AuditAction::ApiTokenRevoked, &client_ip.0).target("api_token", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The API token has been revoked").send();
+    Ok(see_other("/admin/api_tokens"))
+}
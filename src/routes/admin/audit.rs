@@ -0,0 +1,123 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{list_audit_events, AuditAction, AuditEventFilter},
+    utils::{escape_html, none_if_empty, parse_date},
+};
+
+const PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct AuditLogParameters {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    action: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    actor: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    target: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    after: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    before: String,
+    #[serde(default)]
+    page: Option<i64>,
+}
+
+impl AuditLogParameters {
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn with_page(&self, page: i64) -> String {
+        let parameters = AuditLogParameters {
+            page: Some(page),
+            ..self.clone()
+        };
+        serde_urlencoded::to_string(parameters).unwrap()
+    }
+}
+
+#[tracing::instrument(name = "Show audit log", skip_all)]
+pub async fn get_audit_log(
+    parameters: web::Query<AuditLogParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let action = none_if_empty(&parameters.action)
+        .map(AuditAction::try_from)
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let filter = AuditEventFilter {
+        action,
+        actor: none_if_empty(&parameters.actor),
+        target: none_if_empty(&parameters.target),
+        after: parse_date(&parameters.after, 0)?,
+        before: parse_date(&parameters.before, 1)?,
+    };
+    let page = parameters.page();
+    let events = list_audit_events(&pool, &filter, PAGE_SIZE, (page - 1) * PAGE_SIZE)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let total = events.first().map(|e| e.total).unwrap_or(0);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut action_options = String::from(r#"<option value="">Any</option>"#);
+    for a in AuditAction::ALL {
+        let selected = if action == Some(a) { " selected" } else { "" };
+        write!(action_options, r#"<option value="{a}"{selected}>{a}</option>"#).unwrap();
+    }
+
+    let mut events_html = String::new();
+    for e in events {
+        writeln!(
+            events_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            e.occurred_at.format("%Y-%m-%d %H:%M:%S"),
+            escape_html(e.actor.as_deref().unwrap_or("-")),
+            escape_html(&e.action),
+            escape_html(e.target.as_deref().unwrap_or("-")),
+            escape_html(&e.ip),
+        )
+        .unwrap();
+    }
+
+    let mut pagination_html = String::new();
+    if page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/audit?{}">Previous page</a> "#,
+            escape_html(&parameters.with_page(page - 1))
+        )
+        .unwrap();
+    }
+    if page * PAGE_SIZE < total {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/audit?{}">Next page</a>"#,
+            escape_html(&parameters.with_page(page + 1))
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("audit.html"),
+            msg_html,
+            action_options,
+            escape_html(&parameters.actor),
+            escape_html(&parameters.target),
+            escape_html(&parameters.after),
+            escape_html(&parameters.before),
+            total,
+            events_html,
+            pagination_html,
+        )))
+}
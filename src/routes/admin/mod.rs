@@ -10,36 +10,71 @@ use std::fmt::Write;
 use uuid::Uuid;
 
 use crate::{
-    authentication::{change_password, validate_credentials, AuthError, Credentials, UserId},
-    session_state::TypedSession, utils::see_other,
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        change_password, csrf_token, validate_credentials, AuthError, Credentials, Role, UserId,
+    },
+    client_ip::ClientIp,
+    configuration::PasswordHashingSettings,
+    domain::{AdminPassword, PasswordPolicy},
+    session_registry::SessionRegistry, session_state::TypedSession, utils::{escape_html, see_other},
 };
 
+mod account_email;
+mod api_tokens;
+mod audit;
 mod newsletters;
+mod sessions;
+mod subscribers;
+mod two_factor;
+mod users;
 
+pub use account_email::*;
+pub use api_tokens::*;
+pub use audit::*;
 pub use newsletters::*;
+pub use sessions::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
 
 pub async fn admin_dashboard(
+    session: TypedSession,
     user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
     pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = get_username(&user_id.into_inner(), &pool)
             .await
             .map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(format!(include_str!("dashboard.html"), username)))
+        .body(format!(
+            include_str!("dashboard.html"),
+            msg_html,
+            escape_html(&username),
+            role.into_inner(),
+            csrf_token(&session)?
+        )))
 }
 
 pub async fn change_password_get(
+    session: TypedSession,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token(&session)?;
     let mut msgs_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msgs_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(format!(include_str!("change_password.html"), msgs_html)))
+        .body(format!(include_str!("change_password.html"), msgs_html, csrf_token)))
 }
 
 #[derive(serde::Deserialize)]
@@ -49,12 +84,23 @@ pub struct ChangePasswordFormData {
     pub new_password_check: String,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn change_password_post(
     form: web::Form<ChangePasswordFormData>,
+    session: TypedSession,
     user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+    registry: web::Data<SessionRegistry>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if form.new_password != form.new_password_check {
+    let ChangePasswordFormData {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.0;
+    if new_password != new_password_check {
         FlashMessage::error(
             "You entered two different new passwords - the field values must match.".to_string(),
         )
@@ -65,13 +111,20 @@ pub async fn change_password_post(
     let username = get_username(&user_id, &pool)
         .await
         .map_err(actix_web::error::ErrorInternalServerError)?;
+    let new_password = match AdminPassword::parse(new_password, &username, &policy) {
+        Ok(password) => password,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/password"));
+        }
+    };
 
     let credentials = Credentials {
         username,
-        password: form.0.current_password,
+        password: current_password,
     };
 
-    if let Err(e) = validate_credentials(&pool, credentials).await {
+    if let Err(e) = validate_credentials(&pool, credentials, &hashing).await {
         match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect".to_string()).send();
@@ -82,19 +135,51 @@ pub async fn change_password_post(
             }
         }
     }
-    change_password(&pool, user_id, form.0.new_password).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    change_password(&pool, user_id, new_password, &hashing).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::PasswordChanged, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    // Whoever else knew the old password should not stay logged in
+    if let Some(session_id) = session
+        .get_session_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        registry
+            .revoke_others(user_id, session_id)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
     FlashMessage::info("You have successfully changed your password".to_string()).send();
     Ok(see_other("/admin/password"))
 }
 
-pub async fn logout(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+pub async fn logout(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    if let Some(session_id) = session
+        .get_session_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        registry
+            .revoke(user_id, session_id)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::Logout, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
     FlashMessage::info("You have successfully logged out".to_string()).send();
     session.logout();
     Ok(see_other("/login"))
 }
 
 #[tracing::instrument(name = "Get username", skip(pool))]
-async fn get_username(user_id: &Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+pub async fn get_username(user_id: &Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
     let row = sqlx::query!(
         r#"SELECT username
         FROM users
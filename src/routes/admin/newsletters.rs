@@ -11,24 +11,62 @@ use uuid::Uuid;
 use std::fmt::Write;
 
 use crate::{
-    authentication::UserId,
-    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
-    utils::see_other,
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, UserId},
+    client_ip::ClientIp,
+    idempotency::RequestTransaction,
+    session_state::TypedSession,
+    utils::{escape_html, see_other},
 };
 
-pub async fn get_publish_newsletters(flash_messages: IncomingFlashMessages) -> HttpResponse {
+const RECENT_ISSUES: i64 = 10;
+
+pub async fn get_publish_newsletters(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
     let idempotency_key = uuid::Uuid::new_v4();
 
-    HttpResponse::Ok()
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.title, i.published_at, u.username AS "published_by?"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.published_by
+        ORDER BY i.published_at DESC
+        LIMIT $1
+        "#,
+        RECENT_ISSUES,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch recent newsletter issues")
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut issues_html = String::new();
+    for i in issues {
+        writeln!(
+            issues_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&i.title),
+            i.published_at.format("%Y-%m-%d %H:%M"),
+            escape_html(i.published_by.as_deref().unwrap_or("unknown")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
             include_str!("newsletters.html"),
-            msg_html, idempotency_key
-        ))
+            msg_html,
+            csrf_token(&session)?,
+            idempotency_key,
+            issues_html
+        )))
 }
 
 #[derive(serde::Deserialize)]
@@ -36,36 +74,27 @@ pub struct BodyData {
     title: String,
     content_text: String,
     content_html: String,
-    idempotency_key: String,
 }
 
+/// Wrapped in [`Idempotent`](crate::idempotency::Idempotent), keyed by the `idempotency_key`
+/// field of the form.
 #[tracing::instrument(name = "Publish a new newsletter", skip_all, fields(user_id=%&*user_id))]
 pub async fn post_publish_newsletters(
     body: web::Form<BodyData>,
-    pool: web::Data<PgPool>,
+    mut transaction: RequestTransaction,
     user_id: ReqData<UserId>,
+    client_ip: ClientIp,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let BodyData {
         title,
         content_html,
         content_text,
-        idempotency_key,
     } = body.0;
-    let idempotency_key: IdempotencyKey = idempotency_key
-        .try_into()
-        .map_err(actix_web::error::ErrorBadRequest)?;
-
-    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
-        NextAction::StartProcessing(transaction) => transaction,
-        NextAction::ReturnSavedResponse(response) => {
-            success_message().send();
-            return Ok(response);
-        }
-    };
 
     let issue_id = insert_newsletter_issue(
         &mut transaction,
+        *user_id,
         &title,
         &content_html,
         &content_text,
@@ -81,18 +110,22 @@ pub async fn post_publish_newsletters(
         .await
         .context("Failed to enqueue delivery tasks")
         .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut **transaction,
+        AuditEvent::new(*user_id, AuditAction::NewsletterPublished, &client_ip.0)
+            .target("newsletter_issue", issue_id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
 
-    let response = see_other("/admin/newsletters");
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
-        .await
-        .map_err(actix_web::error::ErrorInternalServerError)?;
-    success_message().send();
-    Ok(response)
+    send_newsletter_accepted_message();
+    Ok(see_other("/admin/newsletters"))
 }
 
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
+    published_by: Uuid,
     title: &str,
     text_content: &str,
     html_content: &str,
@@ -105,21 +138,23 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            published_at,
+            published_by
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, now(), $5)
         "#,
         newsletter_issue_id,
         title,
         text_content,
         html_content,
+        published_by,
     );
     transaction.execute(query).await?;
     Ok(newsletter_issue_id)
 }
 
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
 ) -> Result<(), sqlx::Error> {
@@ -139,6 +174,7 @@ async fn enqueue_delivery_tasks(
     Ok(())
 }
 
-fn success_message() -> FlashMessage {
-    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly!")
+/// Also sent when a retried submission gets the saved response back.
+pub fn send_newsletter_accepted_message() {
+    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly!").send();
 }
@@ -0,0 +1,117 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, UserId},
+    client_ip::ClientIp,
+    session_registry::SessionRegistry,
+    session_state::TypedSession,
+    utils::{escape_html, see_other},
+};
+
+pub async fn get_sessions(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    registry: web::Data<SessionRegistry>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token(&session)?;
+    let current_session_id = session
+        .get_session_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let sessions = registry
+        .list(*user_id.into_inner())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut sessions_html = String::new();
+    for (id, s) in sessions {
+        let action = if Some(id) == current_session_id {
+            "This session".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/{id}/revoke" method="post"><input type="hidden" name="csrf_token" value="{csrf_token}" /><button type="submit">Log out</button></form>"#
+            )
+        };
+        writeln!(
+            sessions_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&s.user_agent),
+            escape_html(&s.ip),
+            s.created_at.format("%Y-%m-%d %H:%M"),
+            s.last_seen.format("%Y-%m-%d %H:%M"),
+            action,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("sessions.html"),
+            msg_html, sessions_html, csrf_token
+        )))
+}
+
+#[tracing::instrument(name = "Revoke a session", skip(session, client_ip, pool, registry))]
+pub async fn post_revoke_session(
+    id: web::Path<Uuid>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (user_id, id) = (*user_id.into_inner(), id.into_inner());
+    if !registry
+        .revoke(user_id, id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("Session not found"));
+    }
+    record_audit_event(
+        pool.get_ref(),
+        AuditEvent::new(user_id, AuditAction::SessionRevoked, &client_ip.0).target("session", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let current_session_id = session
+        .get_session_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if current_session_id == Some(id) {
+        session.logout();
+        FlashMessage::info("You have successfully logged out").send();
+        return Ok(see_other("/login"));
+    }
+    FlashMessage::info("The session has been logged out").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Revoke all sessions of the user", skip(session, client_ip, pool, registry))]
+pub async fn post_revoke_all_sessions(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    registry
+        .revoke_all(user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::AllSessionsRevoked, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    session.logout();
+    FlashMessage::info("You have been logged out everywhere").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,86 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, UserId},
+    client_ip::ClientIp,
+    session_state::TypedSession,
+    subscriber_data::{erase_subscriber_data, export_subscriber_data},
+    utils::see_other,
+};
+
+pub async fn get_data_requests(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token(&session)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("data_requests.html"), msg_html, csrf_token)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestParameters {
+    email: String,
+}
+
+pub async fn get_subscriber_data(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let bundle = export_subscriber_data(&pool, parameters.email.trim())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(bundle))
+}
+
+pub async fn post_erase_subscriber_data(
+    form: web::Form<DataRequestParameters>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let report = erase_subscriber_data(&mut transaction, form.email.trim())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    // Recording the email address would defeat the point of erasing it
+    record_audit_event(&mut *transaction, AuditEvent::new(**user_id, AuditAction::SubscriberDataErased, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to erase subscriber data")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if report.total() == 0 {
+        FlashMessage::info("We do not hold any data about this subscriber").send();
+    } else {
+        FlashMessage::info(format!(
+            "All data about the subscriber has been erased ({} records)",
+            report.total()
+        ))
+        .send();
+    }
+    Ok(see_other("/admin/subscribers/data-requests"))
+}
@@ -0,0 +1,184 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Bytes},
+    HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// How many serialized rows can be buffered before the database cursor
+/// has to wait for the client to catch up.
+const EXPORT_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "subscribers.csv",
+            ExportFormat::Json => "subscribers.json",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParameters {
+    format: ExportFormat,
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    status: String,
+}
+
+#[tracing::instrument(name = "Export subscribers", skip(pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let parameters = parameters.into_inner();
+    let format = parameters.format;
+    let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+    let pool = pool.get_ref().clone();
+    tokio::spawn(
+        async move {
+            if let Err(e) = stream_subscribers(&pool, &parameters, &sender).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to export subscribers",
+                );
+                // Abort the response body, the client must not mistake it for a full export
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().into())],
+        })
+        .streaming(body)
+}
+
+type ChunkSender = mpsc::Sender<Result<Bytes, anyhow::Error>>;
+
+/// Serialize every matching subscription and push it down the channel, one row at a time.
+///
+/// Rows are read through a database cursor, therefore memory usage does not depend
+/// on the number of subscribers. Returns early, without error, if the client disconnects.
+async fn stream_subscribers(
+    pool: &PgPool,
+    parameters: &ExportParameters,
+    sender: &ChunkSender,
+) -> Result<(), anyhow::Error> {
+    let format = parameters.format;
+    let mut rows = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, subscribed_at, status
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+        ORDER BY subscribed_at, id
+        "#,
+        parameters.status,
+        parameters.subscribed_after,
+        parameters.subscribed_before,
+    )
+    .fetch(pool);
+
+    let header = match format {
+        ExportFormat::Csv => b"id,email,name,subscribed_at,status\n".to_vec(),
+        ExportFormat::Json => b"[".to_vec(),
+    };
+    if sender.send(Ok(header.into())).await.is_err() {
+        return Ok(());
+    }
+
+    let mut is_first = true;
+    while let Some(record) = rows
+        .try_next()
+        .await
+        .context("Failed to fetch the next subscriber")?
+    {
+        let chunk = match format {
+            ExportFormat::Csv => to_csv_row(&record)?,
+            ExportFormat::Json => {
+                let mut chunk = if is_first { Vec::new() } else { b",".to_vec() };
+                serde_json::to_writer(&mut chunk, &record)
+                    .context("Failed to serialize subscriber as JSON")?;
+                chunk
+            }
+        };
+        is_first = false;
+        if sender.send(Ok(chunk.into())).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    if let ExportFormat::Json = format {
+        let _ = sender.send(Ok(Bytes::from_static(b"]"))).await;
+    }
+    Ok(())
+}
+
+fn to_csv_row(record: &SubscriberRecord) -> Result<Vec<u8>, anyhow::Error> {
+    // Names and emails come from the public signup form
+    let record = SubscriberRecord {
+        email: escape_formula(&record.email),
+        name: escape_formula(&record.name),
+        status: record.status.clone(),
+        ..*record
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer
+        .serialize(&record)
+        .context("Failed to serialize subscriber as CSV")?;
+    writer
+        .into_inner()
+        .context("Failed to flush CSV writer")
+}
+
+/// Keep spreadsheets from evaluating a cell as a formula when the export is opened.
+fn escape_formula(cell: &str) -> String {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", cell)
+    } else {
+        cell.to_owned()
+    }
+}
@@ -0,0 +1,394 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, UserId},
+    client_ip::ClientIp,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    routes::{generate_subscription_token, send_confirmation_email, store_token},
+    session_state::TypedSession,
+    startup::ApplicationBaseUrl,
+    subscriber_data::{erase_subscriber_data, export_subscriber_data},
+    utils::{escape_html, none_if_empty, parse_date, see_other},
+};
+
+const PAGE_SIZE: i64 = 50;
+const STATUSES: [&str; 5] = ["pending_confirmation", "confirmed", "unsubscribed", "bounced", "complained"];
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ListParameters {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    q: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    status: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    subscribed_after: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    subscribed_before: String,
+    #[serde(default)]
+    page: Option<i64>,
+}
+
+impl ListParameters {
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn with_page(&self, page: i64) -> String {
+        let parameters = ListParameters {
+            page: Some(page),
+            ..self.clone()
+        };
+        serde_urlencoded::to_string(parameters).unwrap()
+    }
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    status: String,
+}
+
+#[tracing::instrument(name = "List subscribers", skip_all)]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscribed_after = parse_date(&parameters.subscribed_after, 0)?;
+    let subscribed_before = parse_date(&parameters.subscribed_before, 1)?;
+    let page = parameters.page();
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            id, email, name, subscribed_at, status,
+            count(*) OVER () AS "total!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE '%' || $1 || '%' OR name ILIKE '%' || $1 || '%')
+            AND ($2::text IS NULL OR status = $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscribed_at < $4)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $5
+        OFFSET $6
+        "#,
+        none_if_empty(&parameters.q),
+        none_if_empty(&parameters.status),
+        subscribed_after,
+        subscribed_before,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch subscribers")
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let total = rows.first().map(|r| r.total).unwrap_or(0);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut status_options = String::from(r#"<option value="">Any</option>"#);
+    for status in STATUSES {
+        let selected = if parameters.status == status { " selected" } else { "" };
+        write!(status_options, r#"<option value="{status}"{selected}>{status}</option>"#).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for r in rows {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            r.id,
+            escape_html(&r.email),
+            escape_html(&r.name),
+            r.subscribed_at.format("%Y-%m-%d %H:%M"),
+            escape_html(&r.status),
+        )
+        .unwrap();
+    }
+
+    let mut pagination_html = String::new();
+    if page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/subscribers?{}">Previous page</a> "#,
+            escape_html(&parameters.with_page(page - 1))
+        )
+        .unwrap();
+    }
+    if page * PAGE_SIZE < total {
+        write!(
+            pagination_html,
+            r#"<a href="/admin/subscribers?{}">Next page</a>"#,
+            escape_html(&parameters.with_page(page + 1))
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("subscribers.html"),
+            msg_html,
+            escape_html(&parameters.q),
+            status_options,
+            escape_html(&parameters.subscribed_after),
+            escape_html(&parameters.subscribed_before),
+            total,
+            rows_html,
+            pagination_html,
+        )))
+}
+
+#[tracing::instrument(name = "Fetch subscriber", skip(pool))]
+async fn get_subscriber(pool: &PgPool, id: Uuid) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, subscribed_at, status
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch subscriber")?;
+    Ok(row)
+}
+
+async fn get_subscriber_or_404(pool: &PgPool, id: Uuid) -> Result<SubscriberRow, actix_web::Error> {
+    get_subscriber(pool, id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Subscriber not found"))
+}
+
+#[tracing::instrument(name = "Show subscriber", skip(session, pool, flash_messages))]
+pub async fn subscriber_details(
+    session: TypedSession,
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = get_subscriber_or_404(&pool, id.into_inner()).await?;
+    let history = export_subscriber_data(&pool, &subscriber.email)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut queued_html = String::new();
+    for d in &history.queued_deliveries {
+        writeln!(queued_html, "<li>{}</li>", escape_html(&d.title)).unwrap();
+    }
+    let mut history_html = String::new();
+    for d in &history.delivery_history {
+        writeln!(
+            history_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&d.title),
+            escape_html(&d.outcome),
+            d.attempted_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("subscriber.html"),
+            messages = msg_html,
+            csrf_token = csrf_token(&session)?,
+            id = subscriber.id,
+            email = escape_html(&subscriber.email),
+            name = escape_html(&subscriber.name),
+            subscribed_at = subscriber.subscribed_at.format("%Y-%m-%d %H:%M"),
+            status = escape_html(&subscriber.status),
+            n_tokens = history.subscription_tokens.len(),
+            queued_deliveries = queued_html,
+            delivery_history = history_html,
+        )))
+}
+
+/// Move the subscription to `status`, if it is currently in `from` (or in any status).
+///
+/// Returns `false` if the subscription was left alone.
+#[tracing::instrument(name = "Set subscription status", skip(transaction))]
+async fn set_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    status: &str,
+    from: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let result = transaction
+        .execute(sqlx::query!(
+            "UPDATE subscriptions SET status = $1 WHERE id = $2 AND ($3::text IS NULL OR status = $3)",
+            status,
+            id,
+            from,
+        ))
+        .await
+        .context("Failed to update subscription status")?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn confirm_subscriber(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = get_subscriber_or_404(&pool, id.into_inner()).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let location = format!("/admin/subscribers/{}", subscriber.id);
+    // Checked in the update itself, so a subscription that changed in the meantime is left alone
+    if !set_status(&mut transaction, subscriber.id, "confirmed", Some("pending_confirmation"))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        FlashMessage::error("Only pending subscriptions can be confirmed").send();
+        return Ok(see_other(&location));
+    }
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::SubscriberConfirmed, &client_ip.0).target("subscriber", subscriber.id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to update a subscription status")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The subscription has been confirmed").send();
+    Ok(see_other(&location))
+}
+
+pub async fn unsubscribe_subscriber(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = get_subscriber_or_404(&pool, id.into_inner()).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    set_status(&mut transaction, subscriber.id, "unsubscribed", None)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::SubscriberUnsubscribed, &client_ip.0).target("subscriber", subscriber.id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to update a subscription status")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The subscriber has been unsubscribed").send();
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber.id)))
+}
+
+#[tracing::instrument(name = "Resend confirmation email", skip(user_id, client_ip, pool, email_client, base_url))]
+pub async fn resend_confirmation(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = get_subscriber_or_404(&pool, id.into_inner()).await?;
+    let location = format!("/admin/subscribers/{}", subscriber.id);
+    if subscriber.status != "pending_confirmation" {
+        FlashMessage::error("Only pending subscriptions can be sent a confirmation email").send();
+        return Ok(see_other(&location));
+    }
+    let email = SubscriberEmail::parse(subscriber.email)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let subscription_token = generate_subscription_token();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    store_token(&mut transaction, subscriber.id, &subscription_token)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::ConfirmationResent, &client_ip.0).target("subscriber", subscriber.id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to store a new subscription token")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    send_confirmation_email(&email_client, &email, &base_url.0, &subscription_token)
+        .await
+        .context("Failed to send confirmation email")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    FlashMessage::info("A new confirmation email has been sent").send();
+    Ok(see_other(&location))
+}
+
+pub async fn delete_subscriber(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = get_subscriber_or_404(&pool, id.into_inner()).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    erase_subscriber_data(&mut transaction, &subscriber.email)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    // The subscriber is gone, only its id is kept
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::SubscriberDeleted, &client_ip.0).target("subscriber", subscriber.id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to delete a subscriber")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The subscriber has been deleted").send();
+    Ok(see_other("/admin/subscribers"))
+}
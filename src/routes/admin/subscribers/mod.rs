@@ -0,0 +1,7 @@
+mod data_requests;
+mod export;
+mod manage;
+
+pub use data_requests::*;
+pub use export::*;
+pub use manage::*;
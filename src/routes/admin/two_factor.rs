@@ -0,0 +1,188 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        build_totp, confirm_two_factor_enrolment, csrf_token, disable_two_factor, get_two_factor_status,
+        start_two_factor_enrolment, validate_credentials, AuthError, Credentials,
+        TwoFactorStatus, UserId,
+    },
+    client_ip::ClientIp,
+    configuration::PasswordHashingSettings,
+    session_state::TypedSession,
+    utils::{escape_html, see_other},
+};
+
+use super::get_username;
+
+pub async fn get_two_factor(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let status = get_two_factor_status(&pool, user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let csrf_token = csrf_token(&session)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let content = match status {
+        TwoFactorStatus::Disabled => format!(
+            r#"<p>Two-factor authentication is disabled.</p>
+        <form action="/admin/2fa/enrol" method="post">
+            <input type="hidden" name="csrf_token" value="{csrf_token}" />
+            <button type="submit">Set up two-factor authentication</button>
+        </form>"#
+        ),
+        TwoFactorStatus::Pending { secret } => {
+            let username = get_username(&user_id, &pool)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            let provisioning_uri = build_totp(&secret, &username)
+                .map_err(actix_web::error::ErrorInternalServerError)?
+                .get_url();
+            let qr_code = QrCode::new(provisioning_uri.as_bytes())
+                .map_err(actix_web::error::ErrorInternalServerError)?
+                .render::<svg::Color>()
+                .min_dimensions(200, 200)
+                .build();
+            // Inline the image without its XML declaration
+            let qr_code = qr_code
+                .find("<svg")
+                .map(|start| &qr_code[start..])
+                .unwrap_or(&qr_code);
+            format!(
+                r#"<p>Scan this code with your authenticator app:</p>
+        {}
+        <p>Or enter this key manually: <code id="totp-secret">{}</code></p>
+        <p>Provisioning URI: <code>{}</code></p>
+        <form action="/admin/2fa/confirm" method="post">
+            <input type="hidden" name="csrf_token" value="{csrf_token}" />
+            <label>
+                Code from the app
+                <input type="text" inputmode="numeric" autocomplete="one-time-code" name="code" />
+            </label>
+            <button type="submit">Enable two-factor authentication</button>
+        </form>"#,
+                qr_code,
+                secret,
+                escape_html(&provisioning_uri),
+            )
+        }
+        TwoFactorStatus::Enabled { recovery_codes_left } => format!(
+            r#"<p>Two-factor authentication is enabled. You have {} recovery codes left.</p>
+        <form action="/admin/2fa/disable" method="post">
+            <input type="hidden" name="csrf_token" value="{csrf_token}" />
+            <label>
+                Current password
+                <input type="password" placeholder="Enter current password" name="current_password" />
+            </label>
+            <button type="submit">Disable two-factor authentication</button>
+        </form>"#,
+            recovery_codes_left
+        ),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("two_factor.html"), msg_html, content)))
+}
+
+pub async fn post_two_factor_enrol(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let secret = start_two_factor_enrolment(&pool, *user_id.into_inner())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if secret.is_none() {
+        FlashMessage::error("Two-factor authentication is already enabled").send();
+    }
+    Ok(see_other("/admin/2fa"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmTwoFactorFormData {
+    code: String,
+}
+
+pub async fn post_two_factor_confirm(
+    form: web::Form<ConfirmTwoFactorFormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let username = get_username(&user_id, &pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let recovery_codes = confirm_two_factor_enrolment(&pool, user_id, &username, &form.code)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some(recovery_codes) = recovery_codes else {
+        FlashMessage::error("The code is not valid, please try again").send();
+        return Ok(see_other("/admin/2fa"));
+    };
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::TwoFactorEnabled, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{}</code></li>", code).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("recovery_codes.html"), codes_html)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisableTwoFactorFormData {
+    current_password: String,
+}
+
+pub async fn post_two_factor_disable(
+    form: web::Form<DisableTwoFactorFormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let username = get_username(&user_id, &pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    if let Err(e) = validate_credentials(&pool, credentials, &hashing).await {
+        match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect").send();
+                return Ok(see_other("/admin/2fa"));
+            }
+            AuthError::UnexpectedError(e) => {
+                return Err(actix_web::error::ErrorInternalServerError(e));
+            }
+        }
+    }
+    disable_two_factor(&pool, user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::TwoFactorDisabled, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("Two-factor authentication has been disabled").send();
+    Ok(see_other("/admin/2fa"))
+}
@@ -0,0 +1,356 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        create_invitation, csrf_token, is_user_email_taken, list_pending_invitations, list_users,
+        require_password_reset, set_user_active, set_user_role, Role, UserId,
+    },
+    client_ip::ClientIp,
+    configuration::InvitationSettings,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    session_registry::SessionRegistry,
+    session_state::TypedSession,
+    startup::ApplicationBaseUrl,
+    utils::{escape_html, see_other},
+};
+
+#[tracing::instrument(name = "List users", skip_all)]
+pub async fn get_users(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let users = list_users(&pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let invitations = list_pending_invitations(&pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let csrf_token = csrf_token(&session)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let csrf_input = format!(r#"<input type="hidden" name="csrf_token" value="{csrf_token}" />"#);
+    let mut users_html = String::new();
+    for u in users {
+        let mut status = if u.active { "active" } else { "deactivated" }.to_string();
+        if u.password_reset_required {
+            status.push_str(", must choose a new password");
+        }
+        let mut role_options = String::new();
+        for role in Role::ALL {
+            let selected = if u.role == role.as_str() { " selected" } else { "" };
+            write!(role_options, r#"<option value="{role}"{selected}>{role}</option>"#).unwrap();
+        }
+        let (action, label) = if u.active {
+            ("deactivate", "Deactivate")
+        } else {
+            ("activate", "Reactivate")
+        };
+        writeln!(
+            users_html,
+            r#"<tr><td>{username}</td><td>{email}</td><td>{created_at}</td><td>{role}</td><td>{status}</td><td>
+                <form action="/admin/users/{id}/role" method="post">{csrf_input}<select name="role">{role_options}</select><button type="submit">Change role</button></form>
+                <form action="/admin/users/{id}/{action}" method="post">{csrf_input}<button type="submit">{label}</button></form>
+                <form action="/admin/users/{id}/reset_password" method="post">{csrf_input}<button type="submit">Force password reset</button></form>
+            </td></tr>"#,
+            username = escape_html(&u.username),
+            email = escape_html(u.email.as_deref().unwrap_or_default()),
+            created_at = u.created_at.format("%Y-%m-%d %H:%M"),
+            role = escape_html(&u.role),
+            id = u.user_id,
+        )
+        .unwrap();
+    }
+
+    let mut invitations_html = String::new();
+    for i in invitations {
+        writeln!(
+            invitations_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&i.email),
+            escape_html(&i.role),
+            escape_html(i.invited_by.as_deref().unwrap_or_default()),
+            i.expires_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    let mut invitation_role_options = String::new();
+    for role in Role::ALL {
+        let selected = if role == Role::Viewer { " selected" } else { "" };
+        write!(invitation_role_options, r#"<option value="{role}"{selected}>{role}</option>"#).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("users.html"),
+            msg_html, users_html, csrf_token, invitation_role_options, invitations_html
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct InviteUserFormData {
+    email: String,
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, client_ip, pool, email_client, base_url, settings),
+    fields(user_id = %&*user_id)
+)]
+pub async fn post_invite_user(
+    form: web::Form<InviteUserFormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<InvitationSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteUserFormData { email, role } = form.0;
+    let (email, role) = match (SubscriberEmail::parse(email), Role::try_from(role)) {
+        (Ok(email), Ok(role)) => (email, role),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    if is_user_email_taken(&pool, email.as_ref())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        FlashMessage::error("This email address is used by another account").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let token = create_invitation(&mut transaction, email.as_ref(), role, **user_id, settings.token_ttl())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::UserInvited, &client_ip.0).target("email", email.as_ref()),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to store an invitation")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let invitation_link = format!("{}/invitations/accept?token={}", base_url.0, token);
+    send_invitation_email(&email_client, &email, &invitation_link)
+        .await
+        .context("Failed to send an invitation email")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    FlashMessage::info(format!("An invitation has been sent to {}", email.as_ref())).send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Send invitation email", skip_all)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    invitation_link: &str,
+) -> Result<(), reqwest::Error> {
+    let html_body = format!(
+        "You have been invited to help run our newsletter.<br>\
+        Click <a href=\"{}\">here</a> to create your account.",
+        invitation_link
+    );
+    let text_body = format!(
+        "You have been invited to help run our newsletter.\n\
+        Visit {} to create your account.",
+        invitation_link
+    );
+    email_client
+        .send_email(recipient, "You have been invited", &html_body, &text_body)
+        .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct UserRoleFormData {
+    role: String,
+}
+
+#[tracing::instrument(name = "Change the role of a user", skip(form, client_ip, pool))]
+pub async fn post_user_role(
+    id: web::Path<Uuid>,
+    form: web::Form<UserRoleFormData>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    // Otherwise the last owner could leave nobody able to manage users
+    if id == **user_id {
+        FlashMessage::error("You cannot change your own role").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let role = match Role::try_from(form.0.role) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !set_user_role(&mut transaction, id, role)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("User not found"));
+    }
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::UserRoleChanged, &client_ip.0).target("user", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to change the role of a user")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info(format!("The user is now {}", role)).send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Deactivate a user", skip(client_ip, pool, registry))]
+pub async fn post_deactivate_user(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    if id == **user_id {
+        FlashMessage::error("You cannot deactivate your own account").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !set_user_active(&mut transaction, id, false)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("User not found"));
+    }
+    registry
+        .revoke_all(id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::UserDeactivated, &client_ip.0).target("user", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to deactivate a user")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The user has been deactivated").send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Reactivate a user", skip(client_ip, pool))]
+pub async fn post_activate_user(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !set_user_active(&mut transaction, id, true)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("User not found"));
+    }
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::UserReactivated, &client_ip.0).target("user", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to reactivate a user")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The user has been reactivated").send();
+    Ok(see_other("/admin/users"))
+}
+
+/// Log the user out everywhere, and make them choose a new password when they log back in.
+#[tracing::instrument(name = "Force a password reset", skip(client_ip, pool, registry))]
+pub async fn post_force_password_reset(
+    id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a connection from the pool")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !require_password_reset(&mut transaction, id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Err(actix_web::error::ErrorNotFound("User not found"));
+    }
+    registry
+        .revoke_all(id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent::new(**user_id, AuditAction::PasswordResetForced, &client_ip.0).target("user", id),
+    )
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a transaction to force a password reset")
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    FlashMessage::info("The user will have to choose a new password when they next log in").send();
+    Ok(see_other("/admin/users"))
+}
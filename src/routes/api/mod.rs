@@ -0,0 +1,61 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+
+use crate::authentication::{ApiCaller, ApiScope};
+
+use super::error_chain_fmt;
+
+mod newsletters;
+
+pub use newsletters::*;
+
+/// Errors of the JSON API, reported as `{"error": "..."}`.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("This token does not grant the {0} scope")]
+    MissingScope(ApiScope),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiError::MissingScope(_) => StatusCode::FORBIDDEN,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            ApiError::UnexpectedError(_) => "Something went wrong".to_string(),
+            e => e.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(serde_json::json!({ "error": message }))
+    }
+}
+
+/// Reject the request unless the caller's token grants `scope`.
+fn require_scope(caller: &ApiCaller, scope: ApiScope) -> Result<(), ApiError> {
+    if caller.allows(scope) {
+        Ok(())
+    } else {
+        Err(ApiError::MissingScope(scope))
+    }
+}
+
+/// Report malformed JSON bodies like any other API error.
+pub fn json_error_handler(
+    error: actix_web::error::JsonPayloadError,
+    _req: &actix_web::HttpRequest,
+) -> actix_web::Error {
+    ApiError::ValidationError(error.to_string()).into()
+}
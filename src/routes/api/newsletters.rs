@@ -0,0 +1,95 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{ApiCaller, ApiScope},
+    client_ip::ClientIp,
+    idempotency::RequestTransaction,
+    routes::{enqueue_delivery_tasks, insert_newsletter_issue},
+};
+
+use super::{require_scope, ApiError};
+
+const RECENT_ISSUES: i64 = 20;
+
+#[derive(serde::Serialize)]
+pub struct NewsletterIssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List newsletter issues through the API", skip_all, fields(user_id = %caller.user_id))]
+pub async fn get_api_newsletters(
+    caller: web::ReqData<ApiCaller>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    require_scope(&caller, ApiScope::ReadNewsletters)?;
+    let issues = sqlx::query_as!(
+        NewsletterIssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, published_at
+        FROM newsletter_issues
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        RECENT_ISSUES,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch recent newsletter issues")?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishNewsletterBody {
+    title: String,
+    content_text: String,
+    content_html: String,
+}
+
+/// Publish an issue on behalf of the owner of the API token.
+///
+/// Wrapped in [`Idempotent`](crate::idempotency::Idempotent): retries carrying the same
+/// `Idempotency-Key` header get the original response back.
+#[tracing::instrument(name = "Publish a newsletter through the API", skip_all, fields(user_id = %caller.user_id))]
+pub async fn post_api_newsletters(
+    body: web::Json<PublishNewsletterBody>,
+    mut transaction: RequestTransaction,
+    caller: web::ReqData<ApiCaller>,
+    client_ip: ClientIp,
+) -> Result<HttpResponse, ApiError> {
+    require_scope(&caller, ApiScope::PublishNewsletters)?;
+    let PublishNewsletterBody {
+        title,
+        content_text,
+        content_html,
+    } = body.into_inner();
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        caller.user_id,
+        &title,
+        &content_text,
+        &content_html,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    record_audit_event(
+        &mut **transaction,
+        AuditEvent::new(caller.user_id, AuditAction::NewsletterPublished, &client_ip.0)
+            .target("newsletter_issue", issue_id),
+    )
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "newsletter_issue_id": issue_id,
+    })))
+}
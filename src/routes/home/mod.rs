@@ -1,7 +1,9 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
 
-pub async fn home() -> HttpResponse {
+use crate::form_timing::FormTimer;
+
+pub async fn home(form_timer: web::Data<FormTimer>) -> HttpResponse {
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(include_str!("home.html"), form_timer.issue()))
 }
@@ -0,0 +1,109 @@
+use std::fmt::Write;
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{accept_invitation, get_invitation_email, AcceptInvitationError},
+    configuration::PasswordHashingSettings,
+    domain::{AdminPassword, PasswordPolicy, Username},
+    utils::{escape_html, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    token: String,
+}
+
+fn invitation_page(token: &str) -> String {
+    let query = serde_urlencoded::to_string([("token", token)]).unwrap();
+    format!("/invitations/accept?{}", query)
+}
+
+pub async fn get_accept_invitation(
+    parameters: web::Query<InvitationParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(email) = get_invitation_email(&pool, &parameters.token)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    else {
+        FlashMessage::error(AcceptInvitationError::InvalidToken.to_string()).send();
+        return Ok(see_other("/login"));
+    };
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("accept_invitation.html"),
+            msg_html,
+            escape_html(&email),
+            escape_html(&parameters.token)
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInvitationFormData {
+    token: String,
+    username: String,
+    password: String,
+    password_check: String,
+}
+
+#[tracing::instrument(name = "Accept an invitation", skip_all)]
+pub async fn post_accept_invitation(
+    form: web::Form<AcceptInvitationFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let AcceptInvitationFormData {
+        token,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let username = match Username::parse(&username) {
+        Ok(username) => username,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&invitation_page(&token)));
+        }
+    };
+    let username = username.as_ref();
+    if password != password_check {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&invitation_page(&token)));
+    }
+    let password = match AdminPassword::parse(password, username, &policy) {
+        Ok(password) => password,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&invitation_page(&token)));
+        }
+    };
+    match accept_invitation(&pool, &token, username, password, &hashing).await {
+        Ok(user_id) => {
+            tracing::info!(%user_id, "An invitation has been accepted");
+            FlashMessage::info("Your account has been created, you can now log in").send();
+            Ok(see_other("/login"))
+        }
+        Err(e @ AcceptInvitationError::InvalidToken) => {
+            FlashMessage::error(e.to_string()).send();
+            Ok(see_other("/login"))
+        }
+        Err(e @ (AcceptInvitationError::UsernameTaken | AcceptInvitationError::EmailTaken)) => {
+            FlashMessage::error(e.to_string()).send();
+            Ok(see_other(&invitation_page(&token)))
+        }
+        Err(AcceptInvitationError::UnexpectedError(e)) => {
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
+    }
+}
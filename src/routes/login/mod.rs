@@ -1,25 +1,55 @@
 use std::fmt::Write;
 use actix_web::{
     http::{
-        header::{ContentType, LOCATION},
+        header::{ContentType, LOCATION, USER_AGENT},
         StatusCode,
-    }, web, HttpResponse, ResponseError
+    }, web, HttpRequest, HttpResponse, ResponseError
 };
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use chrono::Utc;
 use sqlx::PgPool;
+use uuid::Uuid;
 
-use crate::{authentication::{validate_credentials, AuthError, Credentials}, session_state::TypedSession};
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        csrf_token, is_two_factor_enabled, rotate_csrf_token, validate_credentials, AuthError, Credentials, LoginThrottle,
+        LoginThrottleDecision, OidcClient,
+    },
+    client_ip::ClientIp,
+    configuration::PasswordHashingSettings,
+    session_registry::SessionRegistry,
+    session_state::TypedSession,
+};
 
 use super::error_chain_fmt;
 
-pub async fn get_login(flash_messages: IncomingFlashMessages) -> HttpResponse {
+mod oidc;
+mod password_reset;
+mod two_factor;
+
+pub use oidc::*;
+pub use password_reset::*;
+pub use two_factor::*;
+
+pub async fn get_login(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    oidc_client: Option<web::Data<OidcClient>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token(&session)?;
     let mut error_html = String::new();
     for m in flash_messages.iter() {
         writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
-    HttpResponse::Ok()
+    let sso_html = if oidc_client.is_some() {
+        r#"<p><a href="/login/oidc">Log in with single sign-on</a></p>"#
+    } else {
+        ""
+    };
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(format!(include_str!("login.html"), error_html))
+        .body(format!(include_str!("login.html"), error_html, csrf_token, sso_html)))
 }
 
 #[derive(serde::Deserialize)]
@@ -32,6 +62,11 @@ pub struct LoginFormData {
 pub enum LoginError {
     #[error("Invalid credentials")]
     AuthError(#[source] anyhow::Error),
+    #[error(
+        "Too many failed login attempts, please try again in {} minutes",
+        .retry_after.as_secs().div_ceil(60)
+    )]
+    LockedOut { retry_after: std::time::Duration },
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -55,26 +90,121 @@ impl ResponseError for LoginError {
     }
 }
 
-#[tracing::instrument(name = "Login", skip(form, pool, session))]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Login",
+    skip(form, request, pool, session, throttle, registry, hashing),
+    fields(client_ip = %client_ip.0)
+)]
 pub async fn post_login(
     form: web::Form<LoginFormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    client_ip: ClientIp,
+    throttle: web::Data<LoginThrottle>,
+    registry: web::Data<SessionRegistry>,
+    hashing: web::Data<PasswordHashingSettings>,
 ) -> Result<HttpResponse, LoginError> {
+    let username = form.0.username;
+    match throttle.check(&username, &client_ip.0).await {
+        Ok(LoginThrottleDecision::LockedOut { retry_after }) => {
+            return Err(LoginError::LockedOut { retry_after });
+        }
+        Ok(LoginThrottleDecision::Proceed { delay, unlocked }) => {
+            if unlocked {
+                FlashMessage::info("The temporary lockout has ended").send();
+            }
+            tokio::time::sleep(delay).await;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to check login throttling. Letting the attempt through.",
+            );
+        }
+    }
+
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
-    let user_id = validate_credentials(&pool, credentials)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
-        })?;
+    let user_id = match validate_credentials(&pool, credentials, &hashing).await {
+        Ok(user_id) => user_id,
+        Err(e @ AuthError::InvalidCredentials(_)) => {
+            record_audit_event(
+                pool.get_ref(),
+                AuditEvent {
+                    actor: None,
+                    action: AuditAction::LoginFailed,
+                    target: Some(format!("username:{}", username)),
+                    ip: &client_ip.0,
+                },
+            )
+            .await?;
+            return Err(match throttle.record_failure(&username, &client_ip.0).await {
+                Ok(Some(lockout)) => LoginError::LockedOut { retry_after: lockout },
+                Ok(None) => LoginError::AuthError(e.into()),
+                Err(throttle_error) => {
+                    tracing::error!(
+                        error.cause_chain = ?throttle_error,
+                        error.message = %throttle_error,
+                        "Failed to record a failed login.",
+                    );
+                    LoginError::AuthError(e.into())
+                }
+            });
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => return Err(LoginError::UnexpectedError(e.into())),
+    };
     tracing::Span::current().record("user_id", tracing::field::display(&user_id));
-    session.renew();
-    session.insert_user_id(user_id).map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    // Failures are only cleared once the second factor is in too, or the password alone
+    // would reset the count of wrong codes
+    if is_two_factor_enabled(&pool, user_id).await? {
+        session.renew();
+        session
+            .insert_pending_two_factor_user_id(user_id)
+            .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+        return Ok(HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/login/2fa"))
+            .finish());
+    }
+    if let Err(e) = throttle.record_success(&username).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to clear failed logins.",
+        );
+    }
+    start_session(&session, &registry, user_id, &client_ip, &request).await?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::Login, &client_ip.0)).await?;
     Ok(HttpResponse::SeeOther()
         .insert_header((LOCATION, "/admin/dashboard"))
         .finish())
 }
+
+/// Log `user_id` in, in a fresh session registered with the `SessionRegistry`.
+pub async fn start_session(
+    session: &TypedSession,
+    registry: &SessionRegistry,
+    user_id: Uuid,
+    client_ip: &ClientIp,
+    request: &HttpRequest,
+) -> Result<(), anyhow::Error> {
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("unknown");
+    session.renew();
+    // Renewing keeps the token handed out before logging in
+    rotate_csrf_token(session)?;
+    let session_id = registry.register(user_id, &client_ip.0, user_agent).await?;
+    session.insert_session_id(session_id)?;
+    session.insert_user_id(user_id)?;
+    let now = Utc::now();
+    session.insert_logged_in_at(now)?;
+    session.insert_last_seen_at(now)?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{find_or_provision_oidc_user, is_two_factor_enabled, OidcClient, OidcUser},
+    client_ip::ClientIp,
+    configuration::PasswordHashingSettings,
+    session_registry::SessionRegistry,
+    session_state::TypedSession,
+    startup::ApplicationBaseUrl,
+    utils::see_other,
+};
+
+use super::start_session;
+
+fn redirect_uri(base_url: &ApplicationBaseUrl) -> String {
+    format!("{}/login/oidc/callback", base_url.0)
+}
+
+fn login_failed(message: &str) -> HttpResponse {
+    FlashMessage::error(message).send();
+    see_other("/login")
+}
+
+/// Send the user to the identity provider to log in.
+#[tracing::instrument(name = "Start single sign-on", skip_all)]
+pub async fn get_login_oidc(
+    session: TypedSession,
+    oidc_client: web::Data<OidcClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (authorization_url, pending) = oidc_client
+        .start_login(&redirect_uri(&base_url))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    session
+        .insert_pending_oidc_login(&pending)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other(&authorization_url))
+}
+
+#[derive(serde::Deserialize)]
+pub struct OidcCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Where the identity provider sends the user back to, with an authorization code.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Finish single sign-on",
+    skip(query, request, session, pool, oidc_client, base_url, registry, hashing),
+    fields(client_ip = %client_ip.0, user_id = tracing::field::Empty)
+)]
+pub async fn get_login_oidc_callback(
+    query: web::Query<OidcCallbackQuery>,
+    request: HttpRequest,
+    session: TypedSession,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    oidc_client: web::Data<OidcClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    registry: web::Data<SessionRegistry>,
+    hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pending = session
+        .get_pending_oidc_login()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    // The state is single use
+    session.remove_pending_oidc_login();
+    let Some(pending) = pending.filter(|p| query.state.as_deref() == Some(p.state.as_str())) else {
+        tracing::warn!("Single sign-on callback with an unexpected state");
+        return Ok(login_failed("Single sign-on failed, please try again"));
+    };
+    if let Some(error) = &query.error {
+        tracing::warn!(error, "The identity provider refused the login");
+        return Ok(login_failed("Single sign-on failed, please try again"));
+    }
+    let Some(code) = &query.code else {
+        return Ok(login_failed("Single sign-on failed, please try again"));
+    };
+
+    let claims = match oidc_client
+        .finish_login(code, &redirect_uri(&base_url), &pending)
+        .await
+    {
+        Ok(claims) => claims,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, error.message = %e, "Invalid single sign-on response");
+            return Ok(login_failed("Single sign-on failed, please try again"));
+        }
+    };
+    let user = find_or_provision_oidc_user(&pool, &claims, oidc_client.settings(), &hashing)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let user_id = match user {
+        OidcUser::Active(user_id) => user_id,
+        OidcUser::Deactivated => return Ok(login_failed("Your account has been deactivated")),
+        OidcUser::Unknown => {
+            return Ok(login_failed("There is no account for this identity, ask an owner for an invitation"))
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // The identity provider stands in for the password, not for the second factor
+    if is_two_factor_enabled(&pool, user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        session.renew();
+        session
+            .insert_pending_two_factor_user_id(user_id)
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        return Ok(see_other("/login/2fa"));
+    }
+    start_session(&session, &registry, user_id, &client_ip, &request)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::Login, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other("/admin/dashboard"))
+}
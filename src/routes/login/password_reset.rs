@@ -0,0 +1,231 @@
+use std::fmt::Write;
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        change_password, consume_password_reset_token, create_password_reset_token,
+        find_user_by_email, get_password_reset_username,
+    },
+    client_ip::ClientIp,
+    configuration::{PasswordHashingSettings, PasswordResetSettings},
+    domain::{AdminPassword, PasswordPolicy, SubscriberEmail},
+    email_client::EmailClient,
+    rate_limit::{RateLimitOutcome, RateLimiter},
+    session_registry::SessionRegistry,
+    startup::ApplicationBaseUrl,
+    utils::{escape_html, see_other},
+};
+
+const INVALID_LINK_MESSAGE: &str = "This password reset link is invalid or has expired";
+
+pub async fn get_forgot_password(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("forgot_password.html"), msg_html))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordFormData {
+    email: String,
+}
+
+/// Email a reset link, if the address belongs to an account.
+///
+/// The response is the same whether it does or not, and the email is sent in the
+/// background so that the response time does not tell either.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip_all,
+    fields(client_ip = %client_ip.0)
+)]
+pub async fn post_forgot_password(
+    form: web::Form<ForgotPasswordFormData>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<PasswordResetSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    FlashMessage::info(
+        "If an account uses this email address, we have sent it a link to reset its password",
+    )
+    .send();
+
+    let rate_limit_key = format!("password_reset:ip:{}", client_ip.0);
+    match rate_limiter.hit(&rate_limit_key, &settings.rate_limit).await {
+        Ok(RateLimitOutcome::Allowed) => {}
+        Ok(RateLimitOutcome::Exceeded { .. }) => {
+            tracing::warn!("Too many password reset requests, not sending anything");
+            return Ok(see_other("/login/forgot"));
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to check the rate limit. Letting the request through.",
+            );
+        }
+    }
+
+    let Some((user_id, email)) = find_user_by_email(&pool, &form.email)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    else {
+        return Ok(see_other("/login/forgot"));
+    };
+    let token = create_password_reset_token(&pool, user_id, settings.token_ttl())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let reset_link = format!("{}/login/reset?token={}", base_url.0, token);
+    tokio::spawn(
+        async move {
+            let recipient = match SubscriberEmail::parse(email) {
+                Ok(recipient) => recipient,
+                Err(e) => {
+                    tracing::error!(error.message = %e, "The email of the user is invalid.");
+                    return;
+                }
+            };
+            if let Err(e) = send_password_reset_email(&email_client, &recipient, &reset_link).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a password reset email.",
+                );
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+    Ok(see_other("/login/forgot"))
+}
+
+#[tracing::instrument(name = "Send password reset email", skip_all)]
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    reset_link: &str,
+) -> Result<(), reqwest::Error> {
+    let html_body = format!(
+        "Someone asked to reset the password of your account.<br>\
+        Click <a href=\"{}\">here</a> to choose a new one.<br>\
+        If it was not you, you can ignore this email.",
+        reset_link
+    );
+    let text_body = format!(
+        "Someone asked to reset the password of your account.\n\
+        Visit {} to choose a new one.\n\
+        If it was not you, you can ignore this email.",
+        reset_link
+    );
+    email_client
+        .send_email(recipient, "Reset your password", &html_body, &text_body)
+        .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordParameters {
+    token: String,
+}
+
+pub async fn get_reset_password(
+    parameters: web::Query<ResetPasswordParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if get_password_reset_username(&pool, &parameters.token)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .is_none()
+    {
+        FlashMessage::error(INVALID_LINK_MESSAGE).send();
+        return Ok(see_other("/login/forgot"));
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("reset_password.html"),
+            msg_html,
+            escape_html(&parameters.token)
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordFormData {
+    token: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+#[tracing::instrument(name = "Reset password", skip_all)]
+pub async fn post_reset_password(
+    form: web::Form<ResetPasswordFormData>,
+    client_ip: ClientIp,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ResetPasswordFormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let query = serde_urlencoded::to_string([("token", &token)]).unwrap();
+    let reset_page = format!("/login/reset?{}", query);
+    if new_password != new_password_check {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&reset_page));
+    }
+    // Checked before the token is used up, so that the user can try another password
+    let Some(username) = get_password_reset_username(&pool, &token)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    else {
+        FlashMessage::error(INVALID_LINK_MESSAGE).send();
+        return Ok(see_other("/login/forgot"));
+    };
+    let new_password = match AdminPassword::parse(new_password, &username, &policy) {
+        Ok(password) => password,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&reset_page));
+        }
+    };
+    let Some(user_id) = consume_password_reset_token(&pool, &token)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    else {
+        FlashMessage::error(INVALID_LINK_MESSAGE).send();
+        return Ok(see_other("/login/forgot"));
+    };
+    change_password(&pool, user_id, new_password, &hashing)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    registry
+        .revoke_all(user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::PasswordReset, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    tracing::info!(%user_id, "The password has been reset");
+    FlashMessage::info("Your password has been reset, you can now log in").send();
+    Ok(see_other("/login"))
+}
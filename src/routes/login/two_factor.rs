@@ -0,0 +1,148 @@
+use std::fmt::Write;
+
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{csrf_token, verify_second_factor, LoginThrottle, LoginThrottleDecision, SecondFactor},
+    client_ip::ClientIp,
+    routes::get_username,
+    session_registry::SessionRegistry,
+    session_state::TypedSession,
+    utils::see_other,
+};
+
+use super::{start_session, LoginError};
+
+pub async fn get_login_two_factor(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session
+        .get_pending_two_factor_user_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .is_none()
+    {
+        return Ok(see_other("/login"));
+    }
+    let csrf_token = csrf_token(&session)?;
+    let mut error_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("two_factor.html"), error_html, csrf_token)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorFormData {
+    code: String,
+}
+
+/// Send the user back to the first login step, e.g. once they are locked out.
+fn restart_login(session: &TypedSession, message: String) -> HttpResponse {
+    session.logout();
+    FlashMessage::error(message).send();
+    see_other("/login")
+}
+
+/// The second login step. Wrong codes count as failed logins, like wrong passwords.
+#[tracing::instrument(
+    name = "Login second factor",
+    skip(form, request, pool, session, throttle, registry),
+    fields(client_ip = %client_ip.0)
+)]
+pub async fn post_login_two_factor(
+    form: web::Form<TwoFactorFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    client_ip: ClientIp,
+    throttle: web::Data<LoginThrottle>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session
+        .get_pending_two_factor_user_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    else {
+        return Ok(see_other("/login"));
+    };
+    let username = get_username(&user_id, &pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    match throttle.check(&username, &client_ip.0).await {
+        Ok(LoginThrottleDecision::LockedOut { retry_after }) => {
+            let e = LoginError::LockedOut { retry_after };
+            return Ok(restart_login(&session, e.to_string()));
+        }
+        Ok(LoginThrottleDecision::Proceed { delay, .. }) => tokio::time::sleep(delay).await,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to check login throttling. Letting the attempt through.",
+            );
+        }
+    }
+
+    let second_factor = verify_second_factor(&pool, user_id, &username, &form.code)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some(second_factor) = second_factor else {
+        record_audit_event(
+            pool.get_ref(),
+            AuditEvent {
+                actor: None,
+                action: AuditAction::LoginFailed,
+                target: Some(format!("username:{}", username)),
+                ip: &client_ip.0,
+            },
+        )
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+        match throttle.record_failure(&username, &client_ip.0).await {
+            Ok(Some(lockout)) => {
+                let e = LoginError::LockedOut { retry_after: lockout };
+                return Ok(restart_login(&session, e.to_string()));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to record a failed login.",
+                );
+            }
+        }
+        FlashMessage::error("Invalid authentication code").send();
+        return Ok(see_other("/login/2fa"));
+    };
+
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    if let Err(e) = throttle.record_success(&username).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to clear failed logins.",
+        );
+    }
+    if let SecondFactor::RecoveryCode { recovery_codes_left } = second_factor {
+        FlashMessage::info(format!(
+            "You have used a recovery code, {} are left",
+            recovery_codes_left
+        ))
+        .send();
+    }
+    session.remove_pending_two_factor_user_id();
+    start_session(&session, &registry, user_id, &client_ip, &request)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    record_audit_event(pool.get_ref(), AuditEvent::new(user_id, AuditAction::Login, &client_ip.0))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other("/admin/dashboard"))
+}
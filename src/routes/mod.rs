@@ -4,6 +4,9 @@ mod subscriptions_confirm;
 mod home;
 mod login;
 mod admin;
+mod api;
+mod invitations;
+mod webhooks;
 
 pub use health_check::*;
 pub use subscriptions::*;
@@ -11,6 +14,9 @@ pub use subscriptions_confirm::*;
 pub use home::*;
 pub use login::*;
 pub use admin::*;
+pub use api::*;
+pub use invitations::*;
+pub use webhooks::*;
 
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
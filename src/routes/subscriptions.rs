@@ -1,6 +1,12 @@
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
-use actix_web::{http::StatusCode, web, HttpResponse, Responder, ResponseError};
+use actix_web::{
+    http::{
+        header::{ContentType, RETRY_AFTER},
+        StatusCode,
+    },
+    web, HttpResponse, Responder, ResponseError,
+};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
@@ -9,8 +15,12 @@ use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    client_ip::ClientIp,
+    configuration::{RateLimitSettings, SubscriptionSettings},
+    domain::{NewSubscriber, SignupPolicy, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    form_timing::FormTimer,
+    rate_limit::{RateLimitOutcome, RateLimiter},
     startup::ApplicationBaseUrl,
 };
 
@@ -20,6 +30,11 @@ use super::error_chain_fmt;
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Honeypot, hidden from people by the form and filled in by bots
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub form_token: String,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -35,33 +50,106 @@ impl TryFrom<FormData> for NewSubscriber {
 
 #[tracing::instrument(
     name = "Addig a new subscriber",
-    skip(form, connection_pool, email_client, base_url),
+    skip_all,
     fields(
         subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_name = %form.name,
+        client_ip = %client_ip.0
     )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    client_ip: ClientIp,
     connection_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    signup_policy: web::Data<SignupPolicy>,
+    form_timer: web::Data<FormTimer>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<impl Responder, SubscribeError> {
-    let subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    if !form.website.is_empty() {
+        // Do not tell bots they have been caught
+        tracing::info!("Ignoring a subscription with the honeypot field filled in");
+        return Ok(HttpResponse::Ok());
+    }
+    form_timer
+        .check(&form.form_token)
+        .map_err(|e| SubscribeError::ValidationError(e.to_string()))?;
+    enforce_rate_limit(&rate_limiter, &format!("subscribe:ip:{}", client_ip.0), &settings.ip_rate_limit).await?;
+
+    let subscriber: NewSubscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    signup_policy.check(&subscriber.email).map_err(SubscribeError::ValidationError)?;
+    let email_key = format!("subscribe:email:{}", subscriber.email.as_ref().to_lowercase());
+    enforce_rate_limit(&rate_limiter, &email_key, &settings.email_rate_limit).await?;
+
     let mut transaction = connection_pool.begin().await
         .context("Failed to acquire a connection from the pool")?;
-    let subscriber_uuid = insert_subscriber(&mut transaction, &subscriber).await
-        .context("Failed to insert new subscriber in the database")?;
+    let subscriber_uuid = match get_existing_subscription(&mut transaction, &subscriber.email).await
+        .context("Failed to look up existing subscriptions")?
+    {
+        // Signing up again before confirming sends a fresh confirmation link
+        Some((subscriber_uuid, status)) if status == "pending_confirmation" => subscriber_uuid,
+        // Nothing to do, and nothing to reveal about the existing subscription
+        Some(_) => return Ok(HttpResponse::Ok()),
+        None => insert_subscriber(&mut transaction, &subscriber).await
+            .context("Failed to insert new subscriber in the database")?,
+    };
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_uuid, &subscription_token).await
         .context("Failed to store the confirmation")?;
     transaction.commit().await
         .context("Failed to commit a transaction to store a new subscriber")?;
-    send_confirmation_email(&email_client, subscriber, &base_url.0, &subscription_token).await
+    send_confirmation_email(&email_client, &subscriber.email, &base_url.0, &subscription_token).await
         .context("Failed to send confirmation email")?;
     Ok(HttpResponse::Ok())
 }
 
+/// Count the request against `key`, letting it through if Redis is unavailable.
+async fn enforce_rate_limit(
+    rate_limiter: &RateLimiter,
+    key: &str,
+    limit: &RateLimitSettings,
+) -> Result<(), SubscribeError> {
+    match rate_limiter.hit(key, limit).await {
+        Ok(RateLimitOutcome::Allowed) => Ok(()),
+        Ok(RateLimitOutcome::Exceeded { retry_after }) => {
+            Err(SubscribeError::TooManyRequests { retry_after })
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to check the rate limit. Letting the request through.",
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Find a subscription for the same address, ignoring case.
+#[tracing::instrument(
+    name = "Looking for an existing subscription",
+    skip(transaction, email)
+)]
+async fn get_existing_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+            SELECT id, status
+            FROM subscriptions
+            WHERE lower(email) = lower($1)
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| (r.id, r.status)))
+}
+
 #[tracing::instrument(
     name = "Saving subscriber in the database",
     skip(transaction, subscriber)
@@ -89,7 +177,7 @@ async fn insert_subscriber(
     name = "Store subscription token",
     skip(transaction, subscriber_uuid, subscription_token)
 )]
-async fn store_token(
+pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_uuid: Uuid,
     subscription_token: &str,
@@ -113,6 +201,8 @@ async fn store_token(
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("Too many subscription requests, please try again later")]
+    TooManyRequests { retry_after: Duration },
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -127,9 +217,20 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             | SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let SubscribeError::TooManyRequests { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+        }
+        response
+            .content_type(ContentType::plaintext())
+            .body(self.to_string())
+    }
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -157,11 +258,11 @@ impl std::error::Error for StoreTokenError {
 
 #[tracing::instrument(
     name = "Send confirmation email",
-    skip(email_client, subscriber, base_url, subscription_token)
+    skip(email_client, recipient, base_url, subscription_token)
 )]
-async fn send_confirmation_email(
+pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    subscriber: NewSubscriber,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
 ) -> Result<(), reqwest::Error> {
@@ -179,12 +280,12 @@ async fn send_confirmation_email(
         confirmation_link
     );
     email_client
-        .send_email(&subscriber.email, "Welcome!", &html_body, &text_body)
+        .send_email(recipient, "Welcome!", &html_body, &text_body)
         .await
 }
 
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
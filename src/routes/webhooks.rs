@@ -0,0 +1,53 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    email_events::{record_email_event, verify_webhook_signature, EmailEventKind, EmailWebhookPayload},
+    startup::EmailWebhookSecret,
+};
+
+const SIGNATURE_HEADER: &str = "Signature";
+const SIGNATURE_TIMESTAMP_HEADER: &str = "Signature-Timestamp";
+
+/// Delivery, bounce, complaint and unsubscribe events, sent by the email provider.
+///
+/// Calls carry a `Signature` of their `Signature-Timestamp` and body, see
+/// [`verify_webhook_signature`].
+#[tracing::instrument(
+    name = "Receive an email event",
+    skip_all,
+    fields(event_type = tracing::field::Empty)
+)]
+pub async fn post_email_events(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    signing_secret: web::Data<EmailWebhookSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+    };
+    let signature = header(SIGNATURE_HEADER);
+    let timestamp = header(SIGNATURE_TIMESTAMP_HEADER);
+    if !verify_webhook_signature(&signing_secret.0, timestamp, &body, signature, Utc::now()) {
+        tracing::warn!("Rejected an email event with a missing or invalid signature");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let payload = std::str::from_utf8(&body).map_err(actix_web::error::ErrorBadRequest)?;
+    let event: EmailWebhookPayload =
+        serde_json::from_str(payload).map_err(actix_web::error::ErrorBadRequest)?;
+    tracing::Span::current().record("event_type", tracing::field::display(&event.event_type));
+
+    // Other events are acknowledged all the same, or the provider would keep sending them
+    if let Some(kind) = EmailEventKind::from_webhook_type(&event.event_type) {
+        record_email_event(&pool, kind, &event, payload)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
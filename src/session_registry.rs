@@ -0,0 +1,236 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use uuid::Uuid;
+
+use crate::configuration::SessionSettings;
+
+/// What we know about a logged-in session, to let its user recognise it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SessionMetadata {
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// The logged-in sessions of every user, kept in Redis next to the session state.
+///
+/// A session is only valid while it is registered here, which lets us log a
+/// user out everywhere without knowing the keys of their session cookies.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    connection: ConnectionManager,
+    key_prefix: String,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+}
+
+impl SessionRegistry {
+    pub fn new(connection: ConnectionManager, key_prefix: String, settings: &SessionSettings) -> Self {
+        Self {
+            connection,
+            key_prefix,
+            idle_timeout: settings.idle_timeout(),
+            absolute_timeout: settings.absolute_timeout(),
+        }
+    }
+
+    /// How long a session created at `created_at` stays registered without being used: until
+    /// it times out, idle or not, rounded up to the second.
+    fn ttl_seconds(&self, created_at: DateTime<Utc>) -> u64 {
+        let remaining = self.absolute_timeout - (Utc::now() - created_at);
+        let ttl_milliseconds = remaining.min(self.idle_timeout).num_milliseconds();
+        (ttl_milliseconds.max(1) as u64).div_ceil(1000)
+    }
+
+    fn session_key(&self, session_id: Uuid) -> String {
+        format!("{}:session:{}", self.key_prefix, session_id)
+    }
+
+    fn user_sessions_key(&self, user_id: Uuid) -> String {
+        format!("{}:user_sessions:{}", self.key_prefix, user_id)
+    }
+
+    /// Register a new session for `user_id`, returning its id.
+    #[tracing::instrument(name = "Register session", skip(self))]
+    pub async fn register(
+        &self,
+        user_id: Uuid,
+        ip: &str,
+        user_agent: &str,
+    ) -> Result<Uuid, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let session_id = Uuid::new_v4();
+        let user_sessions_key = self.user_sessions_key(user_id);
+
+        // Drop the sessions which have expired since the last login
+        let session_ids: Vec<String> = connection
+            .smembers(&user_sessions_key)
+            .await
+            .context("Failed to list the sessions of a user")?;
+        for id in session_ids {
+            let exists: bool = connection
+                .exists(format!("{}:session:{}", self.key_prefix, id))
+                .await
+                .context("Failed to check a session")?;
+            if !exists {
+                connection
+                    .srem::<_, _, ()>(&user_sessions_key, &id)
+                    .await
+                    .context("Failed to forget an expired session")?;
+            }
+        }
+
+        let now = Utc::now();
+        let metadata = SessionMetadata {
+            user_id,
+            created_at: now,
+            last_seen: now,
+            ip: ip.to_string(),
+            user_agent: user_agent.to_string(),
+        };
+        let metadata = serde_json::to_string(&metadata).context("Failed to serialise a session")?;
+        redis::pipe()
+            .atomic()
+            .set_ex(
+                self.session_key(session_id),
+                metadata,
+                self.ttl_seconds(now),
+            )
+            .ignore()
+            .sadd(&user_sessions_key, session_id.to_string())
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await
+            .context("Failed to register a session")?;
+        Ok(session_id)
+    }
+
+    async fn get(&self, session_id: Uuid) -> Result<Option<SessionMetadata>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let metadata: Option<String> = connection
+            .get(self.session_key(session_id))
+            .await
+            .context("Failed to fetch a session")?;
+        // Sessions registered before we kept metadata are treated as expired
+        Ok(metadata.and_then(|m| serde_json::from_str(&m).ok()))
+    }
+
+    /// Whether the session is still registered, extending its lifetime if it is.
+    pub async fn touch(&self, session_id: Uuid) -> Result<bool, anyhow::Error> {
+        let Some(mut metadata) = self.get(session_id).await? else {
+            return Ok(false);
+        };
+        metadata.last_seen = Utc::now();
+        let ttl_seconds = self.ttl_seconds(metadata.created_at);
+        let metadata = serde_json::to_string(&metadata).context("Failed to serialise a session")?;
+        // Only overwrite the session if it has not been revoked in the meantime
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::XX)
+            .with_expiration(SetExpiry::EX(ttl_seconds));
+        let mut connection = self.connection.clone();
+        let updated: Option<String> = connection
+            .set_options(self.session_key(session_id), metadata, options)
+            .await
+            .context("Failed to refresh a session")?;
+        Ok(updated.is_some())
+    }
+
+    /// The live sessions of `user_id`, most recently used first.
+    #[tracing::instrument(name = "List sessions", skip(self))]
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<(Uuid, SessionMetadata)>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let session_ids: Vec<String> = connection
+            .smembers(self.user_sessions_key(user_id))
+            .await
+            .context("Failed to list the sessions of a user")?;
+        let mut sessions = Vec::new();
+        for id in session_ids {
+            let Ok(session_id) = Uuid::parse_str(&id) else {
+                continue;
+            };
+            if let Some(metadata) = self.get(session_id).await? {
+                sessions.push((session_id, metadata));
+            }
+        }
+        sessions.sort_by_key(|(_, s)| std::cmp::Reverse(s.last_seen));
+        Ok(sessions)
+    }
+
+    /// Returns `false` if `session_id` is not a session of `user_id`.
+    #[tracing::instrument(name = "Revoke session", skip(self))]
+    pub async fn revoke(&self, user_id: Uuid, session_id: Uuid) -> Result<bool, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let is_own_session: bool = connection
+            .sismember(self.user_sessions_key(user_id), session_id.to_string())
+            .await
+            .context("Failed to check a session")?;
+        if !is_own_session {
+            return Ok(false);
+        }
+        redis::pipe()
+            .atomic()
+            .del(self.session_key(session_id))
+            .ignore()
+            .srem(self.user_sessions_key(user_id), session_id.to_string())
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await
+            .context("Failed to revoke a session")?;
+        Ok(true)
+    }
+
+    /// Log the user out of every session but `current_session_id`.
+    #[tracing::instrument(name = "Revoke other sessions", skip(self))]
+    pub async fn revoke_others(
+        &self,
+        user_id: Uuid,
+        current_session_id: Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let user_sessions_key = self.user_sessions_key(user_id);
+        let session_ids: Vec<String> = connection
+            .smembers(&user_sessions_key)
+            .await
+            .context("Failed to list the sessions of a user")?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for id in session_ids {
+            if id == current_session_id.to_string() {
+                continue;
+            }
+            pipe.del(format!("{}:session:{}", self.key_prefix, id))
+                .ignore()
+                .srem(&user_sessions_key, &id)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut connection)
+            .await
+            .context("Failed to revoke the other sessions of a user")?;
+        Ok(())
+    }
+
+    /// Log the user out of every session.
+    #[tracing::instrument(name = "Revoke all sessions", skip(self))]
+    pub async fn revoke_all(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let user_sessions_key = self.user_sessions_key(user_id);
+        let session_ids: Vec<String> = connection
+            .smembers(&user_sessions_key)
+            .await
+            .context("Failed to list the sessions of a user")?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for id in session_ids {
+            pipe.del(format!("{}:session:{}", self.key_prefix, id)).ignore();
+        }
+        pipe.del(&user_sessions_key)
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await
+            .context("Failed to revoke the sessions of a user")?;
+        Ok(())
+    }
+}
@@ -2,12 +2,21 @@ use std::future::{ready, Ready};
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::FromRequest;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::authentication::PendingOidcLogin;
+
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &str = "user_id";
+    const PENDING_TWO_FACTOR_USER_ID_KEY: &str = "pending_two_factor_user_id";
+    const SESSION_ID_KEY: &str = "session_id";
+    const CSRF_TOKEN_KEY: &str = "csrf_token";
+    const LOGGED_IN_AT_KEY: &str = "logged_in_at";
+    const LAST_SEEN_AT_KEY: &str = "last_seen_at";
+    const PENDING_OIDC_LOGIN_KEY: &str = "pending_oidc_login";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -21,6 +30,68 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    /// The id of the session in the `SessionRegistry`.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// When the user logged in, to enforce the absolute session timeout.
+    pub fn insert_logged_in_at(&self, at: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::LOGGED_IN_AT_KEY, at)
+    }
+
+    pub fn get_logged_in_at(&self) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.0.get(Self::LOGGED_IN_AT_KEY)
+    }
+
+    /// When the session was last used, to enforce the idle session timeout.
+    pub fn insert_last_seen_at(&self, at: DateTime<Utc>) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::LAST_SEEN_AT_KEY, at)
+    }
+
+    pub fn get_last_seen_at(&self) -> Result<Option<DateTime<Utc>>, SessionGetError> {
+        self.0.get(Self::LAST_SEEN_AT_KEY)
+    }
+
+    /// The user has entered the right password, but still has to provide a second factor.
+    pub fn insert_pending_two_factor_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_TWO_FACTOR_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_two_factor_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_TWO_FACTOR_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_two_factor_user_id(&self) {
+        self.0.remove(Self::PENDING_TWO_FACTOR_USER_ID_KEY);
+    }
+
+    /// The token state-changing forms must send back.
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// The user has been sent to the identity provider to log in.
+    pub fn insert_pending_oidc_login(&self, pending: &PendingOidcLogin) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_OIDC_LOGIN_KEY, pending)
+    }
+
+    pub fn get_pending_oidc_login(&self) -> Result<Option<PendingOidcLogin>, SessionGetError> {
+        self.0.get(Self::PENDING_OIDC_LOGIN_KEY)
+    }
+
+    pub fn remove_pending_oidc_login(&self) {
+        self.0.remove(Self::PENDING_OIDC_LOGIN_KEY);
+    }
+
     pub fn logout(&self) {
         self.0.purge()
     }
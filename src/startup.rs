@@ -1,9 +1,39 @@
 use crate::{
-    authentication::reject_anonymous_users, configuration::{DatabaseSettings, Settings}, email_client::EmailClient, routes::{admin_dashboard, change_password_get, change_password_post, confirm, get_login, get_publish_newsletters, health, home, logout, post_login, post_publish_newsletters, subscribe}
+    authentication::{
+        reject_anonymous_users, reject_invalid_api_tokens, reject_invalid_csrf_tokens,
+        require_editor, require_owner, seed_credentials_present, LoginThrottle, OidcClient,
+    },
+    client_ip::TrustProxyHeaders,
+    configuration::{
+        DatabaseSettings, Environment, IdempotencySettings, InvitationSettings, PasswordHashingSettings,
+        PasswordResetSettings, SessionSettings, Settings, SubscriptionSettings,
+    },
+    domain::{PasswordPolicy, SignupPolicy},
+    email_client::EmailClient,
+    form_timing::FormTimer,
+    idempotency::Idempotent,
+    rate_limit::RateLimiter,
+    routes::{
+        admin_dashboard, change_password_get, change_password_post, confirm, confirm_subscriber,
+        delete_subscriber, export_subscribers, get_accept_invitation, get_account_email,
+        get_api_newsletters, get_api_tokens, get_audit_log, get_data_requests, get_forgot_password, get_login,
+        get_login_oidc, get_login_oidc_callback, get_login_two_factor, get_publish_newsletters, get_reset_password, get_sessions,
+        get_subscriber_data, get_two_factor, get_users, health, home, json_error_handler,
+        list_subscribers, logout, post_accept_invitation, post_account_email, post_activate_user,
+        post_api_newsletters, post_create_api_token, post_deactivate_user, post_email_events,
+        post_erase_subscriber_data, post_force_password_reset, post_forgot_password,
+        post_invite_user, post_login, post_login_two_factor, post_publish_newsletters,
+        post_reset_password, post_revoke_all_sessions, post_revoke_api_token, post_revoke_session,
+        post_two_factor_confirm, post_two_factor_disable, post_two_factor_enrol, post_user_role,
+        resend_confirmation, send_newsletter_accepted_message, subscribe, subscriber_details, unsubscribe_subscriber,
+    },
+    session_registry::SessionRegistry,
 };
-use actix_session::{storage::RedisSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, dev::Server, middleware::from_fn, web, App, HttpServer};
+use anyhow::Context;
+use actix_session::{config::BrowserSession, storage::RedisSessionStore, SessionMiddleware};
+use actix_web::{cookie::{time::Duration, Key}, dev::Server, middleware::from_fn, web, App, HttpServer};
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use redis::aio::ConnectionManager;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
@@ -16,11 +46,53 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let db_connection_pool = get_connection_pool(&configuration.database);
+        if configuration.environment == Environment::Production
+            && seed_credentials_present(&db_connection_pool).await?
+        {
+            anyhow::bail!(
+                "The well-known seed admin credentials are still in use. \
+                Create a new admin with `zero2prod create-admin` and remove or deactivate \
+                the seed user before starting in production."
+            );
+        }
 
+        let email_webhook_secret = configuration
+            .email_client
+            .webhook_signing_secret
+            .clone()
+            .map(EmailWebhookSecret);
+        if configuration.environment == Environment::Production && email_webhook_secret.is_none() {
+            anyhow::bail!(
+                "No webhook signing secret is set for the email provider. Set \
+                `email_client.webhook_signing_secret` before starting in production."
+            );
+        }
         let email_client = configuration
             .email_client
             .client();
 
+        let signup_policy = configuration.subscriptions.signup_policy()?;
+        configuration.password_hashing.params()?;
+        let password_policy = configuration.password_policy.policy()?;
+        let form_timer = FormTimer::new(
+            &configuration.application.hmac_secret,
+            configuration.subscriptions.min_form_fill_time(),
+        );
+        let redis_connection = get_redis_connection(&configuration.redis_uri).await?;
+        let rate_limiter =
+            RateLimiter::new(redis_connection.clone(), configuration.redis_key_prefix.clone());
+        let session_registry = SessionRegistry::new(
+            redis_connection.clone(),
+            configuration.redis_key_prefix.clone(),
+            &configuration.sessions,
+        );
+        let login_throttle = LoginThrottle::new(
+            redis_connection,
+            configuration.redis_key_prefix,
+            configuration.login_throttling,
+        );
+        let oidc_client = configuration.oidc.map(OidcClient::new);
+
         let listen_address = format!(
             "{}:{}",
             configuration.application.http_bind_address, configuration.application.http_listen_port
@@ -32,9 +104,24 @@ impl Application {
             listener,
             db_connection_pool,
             email_client,
+            email_webhook_secret,
             configuration.application.base_url,
             configuration.application.hmac_secret,
             configuration.redis_uri,
+            signup_policy,
+            form_timer,
+            rate_limiter,
+            login_throttle,
+            session_registry,
+            configuration.subscriptions,
+            configuration.password_reset,
+            configuration.invitations,
+            configuration.password_hashing,
+            password_policy,
+            configuration.sessions,
+            configuration.idempotency,
+            oidc_client,
+            TrustProxyHeaders(configuration.application.trust_proxy_headers),
         ).await?;
 
         Ok(Self { port, server })
@@ -53,19 +140,62 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.connect_options())
 }
 
+pub async fn get_redis_connection(redis_uri: &str) -> Result<ConnectionManager, anyhow::Error> {
+    let client = redis::Client::open(redis_uri).context("Invalid Redis URI")?;
+    ConnectionManager::new(client)
+        .await
+        .context("Failed to connect to Redis")
+}
+
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct EmailWebhookSecret(pub String);
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     connection_pool: PgPool,
     email_client: EmailClient,
+    email_webhook_secret: Option<EmailWebhookSecret>,
     base_url: String,
     hmac_secret: String,
     redis_uri: String,
+    signup_policy: SignupPolicy,
+    form_timer: FormTimer,
+    rate_limiter: RateLimiter,
+    login_throttle: LoginThrottle,
+    session_registry: SessionRegistry,
+    subscription_settings: SubscriptionSettings,
+    password_reset_settings: PasswordResetSettings,
+    invitation_settings: InvitationSettings,
+    password_hashing: PasswordHashingSettings,
+    password_policy: PasswordPolicy,
+    session_settings: SessionSettings,
+    idempotency_settings: IdempotencySettings,
+    oidc_client: Option<OidcClient>,
+    trust_proxy_headers: TrustProxyHeaders,
 ) -> Result<Server, anyhow::Error> {
     let connection_pool = web::Data::new(connection_pool);
     let email_client = web::Data::new(email_client);
+    let email_webhook_secret = email_webhook_secret.map(web::Data::new);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let signup_policy = web::Data::new(signup_policy);
+    let form_timer = web::Data::new(form_timer);
+    let rate_limiter = web::Data::new(rate_limiter);
+    let login_throttle = web::Data::new(login_throttle);
+    let session_registry = web::Data::new(session_registry);
+    let password_reset_settings = web::Data::new(password_reset_settings);
+    let invitation_settings = web::Data::new(invitation_settings);
+    let password_hashing = web::Data::new(password_hashing);
+    let password_policy = web::Data::new(password_policy);
+    // Session state in Redis need not outlive the absolute timeout
+    let session_lifecycle = BrowserSession::default()
+        .state_ttl(Duration::seconds(session_settings.absolute_timeout_seconds));
+    let session_settings = web::Data::new(session_settings);
+    let subscription_settings = web::Data::new(subscription_settings);
+    let idempotency_settings = web::Data::new(idempotency_settings);
+    let oidc_client = oidc_client.map(web::Data::new);
+    let trust_proxy_headers = web::Data::new(trust_proxy_headers);
 
     let secret_key = Key::from(hmac_secret.as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -75,28 +205,158 @@ pub async fn run(
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(SessionMiddleware::new(redis_store.clone(), secret_key.clone()))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                    .session_lifecycle(session_lifecycle.clone())
+                    .build(),
+            )
             .wrap(message_framework.clone())
             .wrap(TracingLogger::default())
             .route("/", web::get().to(home))
-            .route("/login", web::get().to(get_login))
-            .route("/login", web::post().to(post_login))
+            .service(
+                web::resource("/login")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
+                    .route(web::get().to(get_login))
+                    .route(web::post().to(post_login)),
+            )
+            .service(
+                web::resource("/login/2fa")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
+                    .route(web::get().to(get_login_two_factor))
+                    .route(web::post().to(post_login_two_factor)),
+            )
+            .configure(|cfg| {
+                // Single sign-on is only offered when a provider is configured
+                if let Some(oidc_client) = &oidc_client {
+                    cfg.app_data(oidc_client.clone())
+                        .route("/login/oidc", web::get().to(get_login_oidc))
+                        .route("/login/oidc/callback", web::get().to(get_login_oidc_callback));
+                }
+            })
+            .route("/login/forgot", web::get().to(get_forgot_password))
+            .route("/login/forgot", web::post().to(post_forgot_password))
+            .route("/login/reset", web::get().to(get_reset_password))
+            .route("/login/reset", web::post().to(post_reset_password))
+            .route("/invitations/accept", web::get().to(get_accept_invitation))
+            .route("/invitations/accept", web::post().to(post_accept_invitation))
             .route("/health_check", web::get().to(health))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .configure(|cfg| {
+                // Unsigned events cannot be trusted, so they are not taken at all
+                if let Some(email_webhook_secret) = &email_webhook_secret {
+                    cfg.app_data(email_webhook_secret.clone())
+                        .route("/webhooks/email-events", web::post().to(post_email_events));
+                }
+            })
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .service(
+                        web::resource("/newsletters")
+                            .wrap(Idempotent::default())
+                            .route(web::get().to(get_api_newsletters))
+                            .route(web::post().to(post_api_newsletters)),
+                    ),
+            )
             .service(
                 web::scope("/admin")
+                    // Runs after `reject_anonymous_users`, so anonymous users are still sent to the login page
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
+                    // Editors and owners
+                    .service(
+                        web::resource("/newsletters")
+                            .wrap(
+                                Idempotent::default()
+                                    .require_key()
+                                    .on_replay(send_newsletter_accepted_message),
+                            )
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(get_publish_newsletters))
+                            .route(web::post().to(post_publish_newsletters)),
+                    )
+                    .service(
+                        web::resource("/subscribers/erase")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(post_erase_subscriber_data)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{id}/confirm")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(confirm_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{id}/unsubscribe")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(unsubscribe_subscriber)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{id}/resend_confirmation")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(resend_confirmation)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{id}/delete")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(delete_subscriber)),
+                    )
+                    // Owners only
+                    .service(
+                        web::scope("/users")
+                            .wrap(from_fn(require_owner))
+                            .route("", web::get().to(get_users))
+                            .route("/invite", web::post().to(post_invite_user))
+                            .route("/{id}/role", web::post().to(post_user_role))
+                            .route("/{id}/deactivate", web::post().to(post_deactivate_user))
+                            .route("/{id}/activate", web::post().to(post_activate_user))
+                            .route("/{id}/reset_password", web::post().to(post_force_password_reset)),
+                    )
+                    .service(
+                        web::resource("/audit")
+                            .wrap(from_fn(require_owner))
+                            .route(web::get().to(get_audit_log)),
+                    )
+                    // Open to every role, registered last so that the paths above take precedence
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::get().to(change_password_get))
                     .route("/password", web::post().to(change_password_post))
+                    .route("/email", web::get().to(get_account_email))
+                    .route("/email", web::post().to(post_account_email))
                     .route("/logout", web::post().to(logout))
-                    .route("/newsletters", web::get().to(get_publish_newsletters))
-                    .route("/newsletters", web::post().to(post_publish_newsletters))
+                    .route("/api_tokens", web::get().to(get_api_tokens))
+                    .route("/api_tokens", web::post().to(post_create_api_token))
+                    .route("/api_tokens/{id}/revoke", web::post().to(post_revoke_api_token))
+                    .route("/sessions", web::get().to(get_sessions))
+                    .route("/sessions/revoke_all", web::post().to(post_revoke_all_sessions))
+                    .route("/sessions/{id}/revoke", web::post().to(post_revoke_session))
+                    .route("/2fa", web::get().to(get_two_factor))
+                    .route("/2fa/enrol", web::post().to(post_two_factor_enrol))
+                    .route("/2fa/confirm", web::post().to(post_two_factor_confirm))
+                    .route("/2fa/disable", web::post().to(post_two_factor_disable))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/data-requests", web::get().to(get_data_requests))
+                    .route("/subscribers/data", web::get().to(get_subscriber_data))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route("/subscribers/{id}", web::get().to(subscriber_details)),
             )
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(signup_policy.clone())
+            .app_data(form_timer.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(login_throttle.clone())
+            .app_data(session_registry.clone())
+            .app_data(password_reset_settings.clone())
+            .app_data(invitation_settings.clone())
+            .app_data(password_hashing.clone())
+            .app_data(password_policy.clone())
+            .app_data(session_settings.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(trust_proxy_headers.clone())
     })
     .listen(listener)?
     .run();
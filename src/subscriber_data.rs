@@ -0,0 +1,260 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriberDataBundle {
+    pub email: String,
+    pub exported_at: DateTime<Utc>,
+    pub subscriptions: Vec<SubscriptionRecord>,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub queued_deliveries: Vec<QueuedDeliveryRecord>,
+    pub delivery_history: Vec<DeliveryHistoryRecord>,
+    pub email_events: Vec<EmailEventRecord>,
+}
+
+impl SubscriberDataBundle {
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+            && self.subscription_tokens.is_empty()
+            && self.queued_deliveries.is_empty()
+            && self.delivery_history.is_empty()
+            && self.email_events.is_empty()
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub status: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriptionTokenRecord {
+    pub subscription_token: String,
+    pub subscriber_id: Uuid,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct QueuedDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DeliveryHistoryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub outcome: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct EmailEventRecord {
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ErasureReport {
+    pub subscriptions: u64,
+    pub subscription_tokens: u64,
+    pub queued_deliveries: u64,
+    pub delivery_history: u64,
+    pub email_events: u64,
+}
+
+impl ErasureReport {
+    pub fn total(&self) -> u64 {
+        self.subscriptions
+            + self.subscription_tokens
+            + self.queued_deliveries
+            + self.delivery_history
+            + self.email_events
+    }
+}
+
+/// Normalise `email` the same way it was normalised when it was stored.
+/// Malformed addresses are looked up as they are, they might predate validation.
+fn normalise(email: &str) -> String {
+    SubscriberEmail::parse(email.to_owned())
+        .map(|e| e.as_ref().to_owned())
+        .unwrap_or_else(|_| email.trim().to_owned())
+}
+
+#[tracing::instrument(name = "Export subscriber data", skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    email: &str,
+) -> Result<SubscriberDataBundle, anyhow::Error> {
+    let email = &normalise(email);
+    // Read everything from a single snapshot so the bundle is consistent
+    let mut transaction = pool.begin().await?;
+    transaction
+        .execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .await?;
+
+    let subscriptions = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, subscribed_at, status
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        "#,
+        email,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch subscriptions")?;
+
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        SELECT subscription_token, subscriber_id
+        FROM subscription_tokens
+        WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))
+        "#,
+        email,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch subscription tokens")?;
+
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDeliveryRecord,
+        r#"
+        SELECT q.newsletter_issue_id, i.title
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(q.subscriber_email) = lower($1)
+        "#,
+        email,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch queued deliveries")?;
+
+    let delivery_history = sqlx::query_as!(
+        DeliveryHistoryRecord,
+        r#"
+        SELECT h.newsletter_issue_id, i.title, h.outcome, h.attempted_at
+        FROM issue_delivery_history h
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE lower(h.subscriber_email) = lower($1)
+        ORDER BY h.attempted_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch delivery history")?;
+
+    let email_events = sqlx::query_as!(
+        EmailEventRecord,
+        r#"
+        SELECT event_type, occurred_at
+        FROM email_events
+        WHERE lower(recipient) = lower($1)
+        ORDER BY occurred_at
+        "#,
+        email,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch email events")?;
+
+    transaction.commit().await?;
+
+    Ok(SubscriberDataBundle {
+        email: email.to_owned(),
+        exported_at: Utc::now(),
+        subscriptions,
+        subscription_tokens,
+        queued_deliveries,
+        delivery_history,
+        email_events,
+    })
+}
+
+/// Hard-delete every row that refers to `email`.
+///
+/// Nothing is gone until the caller commits `transaction`.
+#[tracing::instrument(name = "Erase subscriber data", skip(transaction))]
+pub async fn erase_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<ErasureReport, anyhow::Error> {
+    let email = &normalise(email);
+    let report = erase_in_transaction(transaction, email).await?;
+    tracing::info!(
+        subscriptions = report.subscriptions,
+        subscription_tokens = report.subscription_tokens,
+        queued_deliveries = report.queued_deliveries,
+        delivery_history = report.delivery_history,
+        email_events = report.email_events,
+        "Subscriber data erased",
+    );
+    Ok(report)
+}
+
+async fn erase_in_transaction(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<ErasureReport, anyhow::Error> {
+    let subscription_tokens = transaction
+        .execute(sqlx::query!(
+            r#"
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))
+            "#,
+            email,
+        ))
+        .await
+        .context("Failed to delete subscription tokens")?
+        .rows_affected();
+    let queued_deliveries = transaction
+        .execute(sqlx::query!(
+            "DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)",
+            email,
+        ))
+        .await
+        .context("Failed to delete queued deliveries")?
+        .rows_affected();
+    let delivery_history = transaction
+        .execute(sqlx::query!(
+            "DELETE FROM issue_delivery_history WHERE lower(subscriber_email) = lower($1)",
+            email,
+        ))
+        .await
+        .context("Failed to delete delivery history")?
+        .rows_affected();
+    let email_events = transaction
+        .execute(sqlx::query!(
+            "DELETE FROM email_events WHERE lower(recipient) = lower($1)",
+            email,
+        ))
+        .await
+        .context("Failed to delete email events")?
+        .rows_affected();
+    let subscriptions = transaction
+        .execute(sqlx::query!(
+            "DELETE FROM subscriptions WHERE lower(email) = lower($1)",
+            email,
+        ))
+        .await
+        .context("Failed to delete subscriptions")?
+        .rows_affected();
+    Ok(ErasureReport {
+        subscriptions,
+        subscription_tokens,
+        queued_deliveries,
+        delivery_history,
+        email_events,
+    })
+}
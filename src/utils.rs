@@ -1,7 +1,69 @@
-use actix_web::{http::header::LOCATION, HttpResponse};
+use std::pin::Pin;
+
+use actix_web::{
+    dev::{Payload, ServiceRequest},
+    error::PayloadError,
+    http::header::LOCATION,
+    web, HttpResponse,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::Stream;
 
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((LOCATION, location))
         .finish()
 }
+
+/// Read the body of a request from a middleware, then put it back for the handler to extract.
+pub async fn peek_body(req: &mut ServiceRequest) -> Result<web::Bytes, actix_web::Error> {
+    let body = req.extract::<web::Bytes>().await?;
+    let replay = body.clone();
+    let stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>> =
+        Box::pin(futures_util::stream::once(async move { Ok(replay) }));
+    req.set_payload(Payload::from(stream));
+    Ok(body)
+}
+
+/// Escape user-provided text before embedding it in an HTML page.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turn an optional `YYYY-MM-DD` value, as submitted by a date input, into an instant.
+///
+/// `days_after` shifts the result, so that an inclusive end date can be used as an
+/// exclusive upper bound.
+pub fn parse_date(
+    value: &str,
+    days_after: u64,
+) -> Result<Option<DateTime<Utc>>, actix_web::Error> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(actix_web::error::ErrorBadRequest)?
+        .checked_add_days(chrono::Days::new(days_after))
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Date out of range"))?;
+    Ok(Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+}
+
+pub fn none_if_empty(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
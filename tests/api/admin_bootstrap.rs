@@ -0,0 +1,154 @@
+use zero2prod::authentication::{
+    create_user, seed_credentials_present, CreateUserError, Role, SEED_ADMIN_PASSWORD_HASH,
+};
+use zero2prod::configuration::Environment;
+use zero2prod::domain::AdminPassword;
+use zero2prod::startup::Application;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+async fn store_seed_admin(app: &TestApp) {
+    sqlx::query!(
+        "INSERT INTO users (user_id, username, password_hash) VALUES ($1, 'admin', $2)",
+        uuid::Uuid::new_v4(),
+        SEED_ADMIN_PASSWORD_HASH,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn admin_password(app: &TestApp, username: &str) -> AdminPassword {
+    let policy = app.configuration.password_policy.policy().unwrap();
+    AdminPassword::parse("a-good-password".into(), username, &policy).unwrap()
+}
+
+#[tokio::test]
+async fn migrations_remove_the_seed_admin() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let seed_admin = sqlx::query!("SELECT user_id FROM users WHERE username = 'admin'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(seed_admin.is_none());
+}
+
+#[tokio::test]
+async fn production_refuses_to_start_with_the_seed_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    store_seed_admin(&app).await;
+    let mut configuration = app.configuration.clone();
+    configuration.environment = Environment::Production;
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn the_seed_credentials_cannot_log_in_or_be_upgraded() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.iterations = 3).await;
+    store_seed_admin(&app).await;
+
+    // Act
+    // Whatever the password, the seed admin is treated as an unknown user
+    let response = app.post_login(&serde_json::json!({
+        "username": "admin",
+        "password": "the-seed-password"
+    })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let password_hash = sqlx::query!("SELECT password_hash FROM users WHERE username = 'admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash;
+    assert_eq!(password_hash, SEED_ADMIN_PASSWORD_HASH);
+    assert!(seed_credentials_present(&app.db_pool).await.unwrap());
+}
+
+#[tokio::test]
+async fn development_starts_with_the_seed_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    store_seed_admin(&app).await;
+
+    // Act
+    let outcome = Application::build(app.configuration.clone()).await;
+
+    // Assert
+    assert!(outcome.is_ok());
+}
+
+#[tokio::test]
+async fn production_starts_once_the_seed_admin_is_deactivated() {
+    // Arrange
+    let app = spawn_app().await;
+    store_seed_admin(&app).await;
+    sqlx::query!("UPDATE users SET active = false WHERE username = 'admin'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let mut configuration = app.configuration.clone();
+    configuration.environment = Environment::Production;
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    assert!(outcome.is_ok());
+}
+
+#[tokio::test]
+async fn a_created_admin_can_log_in_as_an_owner() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    create_user(
+        &app.db_pool,
+        "bootstrap",
+        admin_password(&app, "bootstrap"),
+        Role::Owner,
+        &app.configuration.password_hashing,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    let response = app.post_login(&serde_json::json!({
+        "username": "bootstrap",
+        "password": "a-good-password"
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(app.get_admin_dashboard_html().await.contains("Your role is owner."));
+}
+
+#[tokio::test]
+async fn admins_cannot_be_created_with_a_taken_username() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = create_user(
+        &app.db_pool,
+        &app.test_user.username,
+        admin_password(&app, &app.test_user.username),
+        Role::Owner,
+        &app.configuration.password_hashing,
+    )
+    .await;
+
+    // Assert
+    assert!(matches!(outcome, Err(CreateUserError::UsernameTaken(_))));
+}
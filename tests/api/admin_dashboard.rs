@@ -1,4 +1,4 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, login, spawn_app};
 
 #[tokio::test]
 async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
@@ -11,3 +11,20 @@ async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
     // Assert
     assert_is_redirect_to(&response, "/login");
 }
+
+#[tokio::test]
+async fn the_username_is_escaped_on_the_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    sqlx::query!("UPDATE users SET username = '<b>ursula</b>' WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(html_page.contains("Welcome &lt;b&gt;ursula&lt;/b&gt;!"));
+}
@@ -0,0 +1,291 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp};
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        email,
+        name,
+        subscribed_at,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert subscriber");
+    id
+}
+
+async fn subscriber_status(app: &TestApp, id: Uuid) -> Option<String> {
+    sqlx::query!("SELECT status FROM subscriptions WHERE id = $1", id)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+        .map(|r| r.status)
+}
+
+/// The action and target of the last audit event.
+async fn last_audit_event(app: &TestApp) -> (String, Option<String>) {
+    let event = sqlx::query!("SELECT action, target FROM audit_events ORDER BY event_id DESC LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (event.action, event.target)
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation", Utc::now()).await;
+
+    // Act
+    let list = app.get_admin_subscribers("").await;
+    let details = app.get_subscriber_details(id).await;
+    let confirm = app.post_subscriber_action(id, "confirm").await;
+    let delete = app.post_subscriber_action(id, "delete").await;
+
+    // Assert
+    assert_is_redirect_to(&list, "/login");
+    assert_is_redirect_to(&details, "/login");
+    assert_is_redirect_to(&confirm, "/login");
+    assert_is_redirect_to(&delete, "/login");
+    assert_eq!(subscriber_status(&app, id).await.unwrap(), "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula Le Guin", "confirmed", "2024-01-10T00:00:00Z".parse().unwrap()).await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia Butler", "pending_confirmation", "2024-02-10T00:00:00Z".parse().unwrap()).await;
+    insert_subscriber(&app, "ursa.major@example.com", "Ursa Major", "confirmed", "2024-03-10T00:00:00Z".parse().unwrap()).await;
+    login(&app).await;
+
+    // Act - no filter
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("3 subscribers found"));
+
+    // Act - search by name or email
+    let html_page = app.get_admin_subscribers_html("q=urs").await;
+    assert!(html_page.contains("2 subscribers found"));
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("ursa.major@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+
+    // Act - filter by status
+    let html_page = app.get_admin_subscribers_html("status=pending_confirmation").await;
+    assert!(html_page.contains("1 subscribers found"));
+    assert!(html_page.contains("octavia@example.com"));
+
+    // Act - filter by date, both bounds are inclusive
+    let html_page = app
+        .get_admin_subscribers_html("subscribed_after=2024-02-10&subscribed_before=2024-03-10")
+        .await;
+    assert!(html_page.contains("2 subscribers found"));
+    assert!(!html_page.contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    for i in 0..55 {
+        insert_subscriber(
+            &app,
+            &format!("subscriber-{i:02}@example.com"),
+            "Subscriber",
+            "confirmed",
+            now - Duration::minutes(i),
+        )
+        .await;
+    }
+    login(&app).await;
+
+    // Act - first page
+    let html_page = app.get_admin_subscribers_html("status=confirmed").await;
+
+    // Assert
+    assert!(html_page.contains("55 subscribers found"));
+    assert!(html_page.contains("subscriber-00@example.com"));
+    assert!(html_page.contains("subscriber-49@example.com"));
+    assert!(!html_page.contains("subscriber-50@example.com"));
+    assert!(html_page.contains(r#"<a href="/admin/subscribers?status=confirmed&amp;page=2">Next page</a>"#));
+
+    // Act - second page
+    let html_page = app.get_admin_subscribers_html("status=confirmed&page=2").await;
+
+    // Assert
+    assert!(html_page.contains("subscriber-50@example.com"));
+    assert!(html_page.contains("subscriber-54@example.com"));
+    assert!(!html_page.contains("subscriber-49@example.com"));
+    assert!(!html_page.contains("Next page"));
+    assert!(html_page.contains("Previous page"));
+}
+
+#[tokio::test]
+async fn subscriber_details_show_the_subscription() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula Le Guin", "confirmed", Utc::now()).await;
+    login(&app).await;
+
+    // Act
+    let html_page = app.get_subscriber_details_html(id).await;
+
+    // Assert
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("Ursula Le Guin"));
+    assert!(html_page.contains(&format!("/admin/subscribers/{}/unsubscribe", id)));
+}
+
+#[tokio::test]
+async fn details_of_an_unknown_subscriber_are_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscriber_details(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_confirmed_and_unsubscribed_manually() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation", Utc::now()).await;
+    login(&app).await;
+
+    // Act - part 1 - confirm
+    let response = app.post_subscriber_action(id, "confirm").await;
+
+    // Assert - part 1
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    assert_eq!(subscriber_status(&app, id).await.unwrap(), "confirmed");
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("<p><i>The subscription has been confirmed</i></p>"));
+    let target = Some(format!("subscriber:{}", id));
+    assert_eq!(last_audit_event(&app).await, ("subscriber_confirmed".into(), target.clone()));
+
+    // Act - part 2 - unsubscribe
+    let response = app.post_subscriber_action(id, "unsubscribe").await;
+
+    // Assert - part 2
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    assert_eq!(subscriber_status(&app, id).await.unwrap(), "unsubscribed");
+    assert_eq!(last_audit_event(&app).await, ("subscriber_unsubscribed".into(), target));
+}
+
+#[tokio::test]
+async fn only_pending_subscriptions_can_be_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "unsubscribed", Utc::now()).await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_subscriber_action(id, "confirm").await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    assert_eq!(subscriber_status(&app, id).await.unwrap(), "unsubscribed");
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("<p><i>Only pending subscriptions can be confirmed</i></p>"));
+    assert_ne!(last_audit_event(&app).await.0, "subscriber_confirmed");
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_issues_a_new_token_by_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation", Utc::now()).await;
+    login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriber_action(id, "resend_confirmation").await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    assert_eq!(
+        last_audit_event(&app).await,
+        ("confirmation_resent".into(), Some(format!("subscriber:{}", id)))
+    );
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(subscriber_status(&app, id).await.unwrap(), "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_is_not_resent_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed", Utc::now()).await;
+    login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriber_action(id, "resend_confirmation").await;
+
+    // Assert
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("Only pending subscriptions can be sent a confirmation email"));
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed", Utc::now()).await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_subscriber_action(id, "delete").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    assert!(subscriber_status(&app, id).await.is_none());
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("<p><i>The subscriber has been deleted</i></p>"));
+    assert_eq!(
+        last_audit_event(&app).await,
+        ("subscriber_deleted".into(), Some(format!("subscriber:{}", id)))
+    );
+}
@@ -0,0 +1,340 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp, TestUser};
+
+/// Invite `email` and return the token from the invitation email.
+async fn invite(app: &TestApp, email: &str) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_invite_user(email, "editor").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let invitation_link = app.get_confirmation_links(&email_request).html;
+    assert_eq!(invitation_link.path(), "/invitations/accept");
+    invitation_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .expect("The invitation link has no token")
+}
+
+async fn store_other_user(app: &TestApp) -> TestUser {
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    user
+}
+
+async fn login_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &user.username,
+        "password": &user.password
+    })).await
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_invite_user("new-user@example.com", "viewer").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn users_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = store_other_user(&app).await;
+    login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_users_html().await;
+
+    // Assert
+    assert!(html_page.contains(&app.test_user.username));
+    assert!(html_page.contains(&other_user.username));
+    assert!(html_page.contains(&other_user.email));
+}
+
+#[tokio::test]
+async fn an_invited_user_can_create_an_account_and_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act - part 1 - Invite
+    let token = invite(&app, "new-user@example.com").await;
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("An invitation has been sent to new-user@example.com"));
+    assert!(html_page.contains("<td>new-user@example.com</td>"));
+
+    // Act - part 2 - Accept
+    app.post_logout().await;
+    let response = app.get_accept_invitation(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": "new-user",
+            "password": "a-good-password",
+            "password_check": "a-good-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("Your account has been created, you can now log in"));
+
+    // Act - part 3 - Log in
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new-user",
+            "password": "a-good-password",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let user = sqlx::query!("SELECT email, role FROM users WHERE username = 'new-user'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.email.as_deref(), Some("new-user@example.com"));
+    assert_eq!(user.role, "editor");
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = invite(&app, "new-user@example.com").await;
+    let body = serde_json::json!({
+        "token": &token,
+        "username": "new-user",
+        "password": "a-good-password",
+        "password_check": "a-good-password",
+    });
+    let response = app.post_accept_invitation(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": "another-user",
+            "password": "a-good-password",
+            "password_check": "a-good-password",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains("This invitation is invalid or has expired"));
+    let response = app.get_accept_invitation(&token).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_invitation_cannot_take_an_existing_username() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = invite(&app, "new-user@example.com").await;
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": &app.test_user.username,
+            "password": "a-good-password",
+            "password_check": "a-good-password",
+        }))
+        .await;
+
+    // Assert
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("/invitations/accept?token="));
+    // The invitation can still be used
+    let response = app.get_accept_invitation(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("This username is already taken"));
+}
+
+#[tokio::test]
+async fn an_invitation_cannot_be_accepted_with_a_weak_password() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = invite(&app, "new-user@example.com").await;
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": "new-user",
+            "password": "password1234",
+            "password_check": "password1234",
+        }))
+        .await;
+
+    // Assert
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("/invitations/accept?token="));
+    let response = app.get_accept_invitation(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("This password has appeared in a data breach"));
+}
+
+#[tokio::test]
+async fn an_invitation_cannot_be_accepted_with_an_unsafe_username() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = invite(&app, "new-user@example.com").await;
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": &token,
+            "username": "<script>alert(1)</script>",
+            "password": "a-good-password",
+            "password_check": "a-good-password",
+        }))
+        .await;
+
+    // Assert
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("/invitations/accept?token="));
+    let response = app.get_accept_invitation(&token).await;
+    assert!(response.text().await.unwrap().contains("The username can only contain"));
+    let users = sqlx::query!(r#"SELECT count(*) AS "count!" FROM users"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, 1);
+}
+
+#[tokio::test]
+async fn users_cannot_be_invited_twice() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_invite_user(&app.test_user.email, "viewer").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("This email address is used by another account"));
+}
+
+#[tokio::test]
+async fn deactivated_users_are_logged_out_and_cannot_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = store_other_user(&app).await;
+    let other_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = app
+        .post_form_with(&other_client, "/login", &serde_json::json!({
+            "username": &other_user.username,
+            "password": &other_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    login(&app).await;
+
+    // Act
+    let response = app.post_user_action(other_user.user_id, "deactivate").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    assert!(app.get_admin_users_html().await.contains("The user has been deactivated"));
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    app.post_logout().await;
+    let response = login_as(&app, &other_user).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn reactivated_users_can_log_in_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = store_other_user(&app).await;
+    login(&app).await;
+    app.post_user_action(other_user.user_id, "deactivate").await;
+
+    // Act
+    let response = app.post_user_action(other_user.user_id, "activate").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    app.post_logout().await;
+    let response = login_as(&app, &other_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn users_cannot_deactivate_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_user_action(app.test_user.user_id, "deactivate").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    assert!(app.get_admin_users_html().await.contains("You cannot deactivate your own account"));
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_forced_password_reset_must_be_completed_before_anything_else() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = store_other_user(&app).await;
+    login(&app).await;
+    let response = app.post_user_action(other_user.user_id, "reset_password").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    app.post_logout().await;
+
+    // Act - part 1 - Log in
+    let response = login_as(&app, &other_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("You must choose a new password before going any further"));
+
+    // Act - part 2 - Change the password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &other_user.password,
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,354 @@
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, login, spawn_app, TestApp, TestUser,
+};
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+    })
+}
+
+async fn count_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn created_tokens_are_shown_once_and_stored_hashed() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+
+    // Assert
+    assert!(token.starts_with("z2p_"));
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("Test token"));
+    assert!(html_page.contains("newsletters:publish"));
+    assert!(!html_page.contains(&token));
+    let stored = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_with_an_api_token() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    app.post_logout().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_api_newsletters(&token, &newsletter_body(), None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["newsletter_issue_id"].is_string());
+    let issue = sqlx::query!("SELECT published_by, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.published_by, Some(app.test_user.user_id));
+    assert_eq!(issue.text_content, "Text content");
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/api/v1/newsletters", &app.address))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .unwrap();
+    let invalid_token_response = app
+        .post_api_newsletters("z2p_not-a-real-token", &newsletter_body(), None)
+        .await;
+
+    // Assert
+    for response in [response, invalid_token_response] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers().contains_key("WWW-Authenticate"));
+    }
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    // Act
+    let response = app
+        .post_form(&format!("/admin/api_tokens/{}/revoke", token_id), &())
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let response = app.post_api_newsletters(&token, &newsletter_body(), None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(!app.get_api_tokens_html().await.contains("Test token"));
+}
+
+#[tokio::test]
+async fn tokens_can_only_be_used_within_their_scopes() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+
+    // Act
+    let read_response = app.get_api_newsletters(&token).await;
+    let publish_response = app.post_api_newsletters(&token, &newsletter_body(), None).await;
+
+    // Assert
+    assert_eq!(read_response.status().as_u16(), 200);
+    assert_eq!(publish_response.status().as_u16(), 403);
+    let body: serde_json::Value = publish_response.json().await.unwrap();
+    assert_eq!(body["error"], "This token does not grant the newsletters:publish scope");
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn tokens_lose_scopes_their_owner_no_longer_has_the_role_for() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    sqlx::query!(
+        "UPDATE users SET role = 'viewer' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_api_newsletters(&token, &newsletter_body(), None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn tokens_of_deactivated_users_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    sqlx::query!(
+        "UPDATE users SET active = false WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_api_newsletters(&token, &newsletter_body(), None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn viewers_cannot_create_publishing_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut viewer = TestUser::generate();
+    viewer.role = "viewer".to_string();
+    viewer.store(&app.db_pool).await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &viewer.username,
+            "password": &viewer.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let response = app
+        .post_create_api_token("Test token", &["newsletters:publish"])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("You need the editor role to use the newsletters:publish scope"));
+    let tokens = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+}
+
+#[tokio::test]
+async fn api_publishing_honours_the_idempotency_key_header() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let first = app
+        .post_api_newsletters(&token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+    let second = app
+        .post_api_newsletters(&token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    assert_eq!(count_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn malformed_bodies_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &serde_json::json!({ "title": "Title" }), None)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"].is_string());
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_to_the_api_token() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let first_token = app.create_api_token(&["newsletters:publish"]).await;
+    let second_token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": &idempotency_key
+    })).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    let first = app
+        .post_api_newsletters(&first_token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+    let second = app
+        .post_api_newsletters(&second_token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_ne!(first.text().await.unwrap(), second.text().await.unwrap());
+    assert_eq!(count_issues(&app).await, 3);
+}
+
+#[tokio::test]
+async fn failed_requests_are_not_saved_for_their_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_api_newsletters(&token, &serde_json::json!({ "title": "Title" }), Some(&idempotency_key))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(count_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &newsletter_body(), Some(&"a".repeat(100)))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"].is_string());
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_with_a_different_payload_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_api_newsletters(&token, &newsletter_body(), Some(&idempotency_key))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let mut other_body = newsletter_body();
+    other_body["title"] = "Another title".into();
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &other_body, Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "Idempotency key reused with different payload");
+    assert_eq!(count_issues(&app).await, 1);
+}
@@ -0,0 +1,209 @@
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp, TestUser};
+
+struct AuditEventRecord {
+    actor_id: Option<uuid::Uuid>,
+    action: String,
+    target: Option<String>,
+    ip: String,
+}
+
+async fn audit_events(app: &TestApp) -> Vec<AuditEventRecord> {
+    sqlx::query_as!(
+        AuditEventRecord,
+        "SELECT actor_id, action, target, ip FROM audit_events ORDER BY event_id",
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn logins_and_logouts_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    login(&app).await;
+    app.post_logout().await;
+
+    // Assert
+    let events = audit_events(&app).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].action, "login");
+    assert_eq!(events[1].action, "logout");
+    for event in events {
+        assert_eq!(event.actor_id, Some(app.test_user.user_id));
+        assert_eq!(event.ip, "127.0.0.1");
+    }
+}
+
+#[tokio::test]
+async fn failed_logins_are_recorded_without_an_actor() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+
+    // Assert
+    let events = audit_events(&app).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].action, "login_failed");
+    assert_eq!(events[0].actor_id, None);
+    assert_eq!(
+        events[0].target.as_deref(),
+        Some(format!("username:{}", app.test_user.username).as_str())
+    );
+}
+
+#[tokio::test]
+async fn password_changes_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let events = audit_events(&app).await;
+    assert_eq!(events.last().unwrap().action, "password_changed");
+    assert_eq!(events.last().unwrap().actor_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn published_issues_are_recorded_with_their_id() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    // Assert
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let events = audit_events(&app).await;
+    let event = events.last().unwrap();
+    assert_eq!(event.action, "newsletter_published");
+    assert_eq!(event.target.as_deref(), Some(format!("newsletter_issue:{}", issue_id).as_str()));
+}
+
+#[tokio::test]
+async fn owner_actions_on_users_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    login(&app).await;
+
+    // Act
+    app.post_user_action(other_user.user_id, "deactivate").await;
+
+    // Assert
+    let events = audit_events(&app).await;
+    let event = events.last().unwrap();
+    assert_eq!(event.action, "user_deactivated");
+    assert_eq!(event.actor_id, Some(app.test_user.user_id));
+    assert_eq!(event.target.as_deref(), Some(format!("user:{}", other_user.user_id).as_str()));
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": "someone-else",
+        "password": "wrong-password"
+    }))
+    .await;
+    login(&app).await;
+
+    // Act
+    let all_events = app.get_audit_log_html("").await;
+    let failed_logins = app.get_audit_log_html("action=login_failed").await;
+    let by_actor = app
+        .get_audit_log_html(&format!("actor={}", app.test_user.username))
+        .await;
+
+    // Assert
+    assert!(all_events.contains("2 events found"));
+    assert!(failed_logins.contains("1 events found"));
+    assert!(failed_logins.contains("username:someone-else"));
+    assert!(by_actor.contains("1 events found"));
+    assert!(by_actor.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn an_unknown_action_filter_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_audit_log("action=not_an_action").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut editor = TestUser::generate();
+    editor.role = "editor".to_string();
+    editor.store(&app.db_pool).await;
+    app.post_login(&serde_json::json!({
+        "username": &editor.username,
+        "password": &editor.password
+    }))
+    .await;
+
+    // Act
+    let response = app.get_audit_log("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn audit_events_cannot_be_changed_or_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let update = sqlx::query!("UPDATE audit_events SET action = 'nothing'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_events")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    assert_eq!(audit_events(&app).await.len(), 1);
+}
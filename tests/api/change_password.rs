@@ -1,6 +1,6 @@
 use uuid::Uuid;
 
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, login, spawn_app};
 
 #[tokio::test]
 async fn you_must_be_logged_in_to_see_the_change_password_form() {
@@ -152,3 +152,36 @@ async fn changing_password_works() {
     })).await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn new_passwords_must_comply_with_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let cases = [
+        ("a".to_string(), "The password must be at least 12 characters long"),
+        ("x7#Kp2!qZ9a-".repeat(11), "The password must be at most 128 characters long"),
+        (app.test_user.username.to_uppercase(), "The password cannot be the same as your username"),
+        ("Password1234".to_string(), "This password has appeared in a data breach"),
+        ("aaaaaaaaaaaaaaaa".to_string(), "This password is too easy to guess"),
+    ];
+
+    for (new_password, message) in cases {
+        // Act
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/password");
+        let html = app.get_change_password_html().await;
+        assert!(html.contains(message), "{} was not rejected with: {}", new_password, message);
+    }
+    // The password is unchanged
+    app.post_logout().await;
+    login(&app).await;
+}
@@ -0,0 +1,170 @@
+use crate::helpers::{assert_is_redirect_to, login, spawn_app};
+
+#[tokio::test]
+async fn forms_embed_a_csrf_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Before logging in
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"name="csrf_token""#));
+
+    // Act - Part 2 - Once logged in
+    login(&app).await;
+    let token = app.get_csrf_token(&app.api_client).await;
+    for html_page in [
+        app.get_admin_dashboard_html().await,
+        app.get_change_password_html().await,
+        app.get_publish_newsletter_html().await,
+        app.get_admin_users_html().await,
+    ] {
+        assert!(html_page.contains(&format!(r#"name="csrf_token" value="{}""#, token)));
+    }
+}
+
+#[tokio::test]
+async fn login_without_a_csrf_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.get_login_html().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn admin_forms_without_a_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn admin_forms_with_an_invalid_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .form(&serde_json::json!({
+            "title": "Newsletter title",
+            "content_text": "Text content",
+            "content_html": "<p>Html content</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "csrf_token": "not-the-token",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn the_csrf_token_is_rotated_when_logging_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let token_before_login = app.get_csrf_token(&app.api_client).await;
+
+    // Act
+    login(&app).await;
+
+    // Assert
+    let token_after_login = app.get_csrf_token(&app.api_client).await;
+    assert_ne!(token_before_login, token_after_login);
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({ "csrf_token": &token_before_login }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn csrf_tokens_from_another_session_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let attacker_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let attacker_token = app.get_csrf_token(&attacker_client).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({ "csrf_token": attacker_token }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn anonymous_users_are_still_sent_to_the_login_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/password", &app.address))
+        .form(&serde_json::json!({
+            "current_password": "password",
+            "new_password": "new-password",
+            "new_password_check": "new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
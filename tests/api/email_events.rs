@@ -0,0 +1,220 @@
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+use zero2prod::{configuration::Environment, startup::Application};
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp};
+
+/// A sample payload of the email provider, sent to `recipient`.
+fn sample_event(name: &str, recipient: &str) -> String {
+    let path = format!("{}/tests/api/fixtures/email_events/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(path)
+        .expect("Failed to read the sample email event")
+        .replace("jane@example.com", recipient)
+}
+
+fn sign(app: &TestApp, timestamp: &str, body: &str) -> String {
+    let secret = app.configuration.email_client.webhook_signing_secret.as_deref().unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn post_signed(app: &TestApp, body: &str) -> reqwest::Response {
+    let timestamp = Utc::now().timestamp().to_string();
+    app.post_email_events(body, Some(&timestamp), Some(&sign(app, &timestamp, body))).await
+}
+
+async fn store_confirmed_subscriber(app: &TestApp, email: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'Jane', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+        email,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscription_status(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn count_email_events(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn events_without_a_valid_signature_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    store_confirmed_subscriber(&app, "jane@example.com").await;
+    let body = sample_event("hard_bounced", "jane@example.com");
+    let timestamp = Utc::now().timestamp().to_string();
+
+    for signature in [None, Some("not-a-signature"), Some(&sign(&app, &timestamp, "{}")[..])] {
+        // Act
+        let response = app.post_email_events(&body, Some(&timestamp), signature).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401, "Accepted signature {:?}", signature);
+    }
+    assert_eq!(count_email_events(&app).await, 0);
+    assert_eq!(subscription_status(&app, "jane@example.com").await, "confirmed");
+}
+
+#[tokio::test]
+async fn events_signed_too_long_ago_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    store_confirmed_subscriber(&app, "jane@example.com").await;
+    let body = sample_event("hard_bounced", "jane@example.com");
+    let stale = (Utc::now() - Duration::minutes(10)).timestamp().to_string();
+
+    // Act
+    let response = app.post_email_events(&body, Some(&stale), Some(&sign(&app, &stale, &body))).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(count_email_events(&app).await, 0);
+    assert_eq!(subscription_status(&app, "jane@example.com").await, "confirmed");
+}
+
+#[tokio::test]
+async fn sample_events_are_stored_and_update_the_subscription() {
+    // Arrange
+    let app = spawn_app().await;
+    let cases = [
+        ("delivered", "confirmed"),
+        ("soft_bounced", "confirmed"),
+        ("hard_bounced", "bounced"),
+        ("spam_complaint", "complained"),
+        ("unsubscribed", "unsubscribed"),
+    ];
+
+    for (event, expected_status) in cases {
+        let recipient = format!("{}@example.com", event);
+        store_confirmed_subscriber(&app, &recipient).await;
+
+        // Act
+        let response = post_signed(&app, &sample_event(event, &recipient)).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 204);
+        assert_eq!(subscription_status(&app, &recipient).await, expected_status, "After {}", event);
+        let stored = sqlx::query!(
+            "SELECT event_type, payload->'data'->>'id' AS provider_id FROM email_events WHERE recipient = $1",
+            recipient,
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        assert_eq!(stored.event_type, event);
+        assert!(stored.provider_id.is_some());
+    }
+}
+
+#[tokio::test]
+async fn replayed_events_are_stored_once() {
+    // Arrange
+    let app = spawn_app().await;
+    store_confirmed_subscriber(&app, "jane@example.com").await;
+    let body = sample_event("spam_complaint", "jane@example.com");
+
+    // Act
+    let first = post_signed(&app, &body).await;
+    let second = post_signed(&app, &body).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 204);
+    assert_eq!(second.status().as_u16(), 204);
+    assert_eq!(count_email_events(&app).await, 1);
+}
+
+#[tokio::test]
+async fn untracked_events_are_acknowledged_and_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_signed(&app, &sample_event("opened", "jane@example.com")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(count_email_events(&app).await, 0);
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = r#"{"type": "activity.hard_bounced"}"#;
+
+    // Act
+    let response = post_signed(&app, body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_bounced_or_complaining_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    for (recipient, event) in [
+        ("bounced@example.com", "hard_bounced"),
+        ("complained@example.com", "spam_complaint"),
+    ] {
+        store_confirmed_subscriber(&app, recipient).await;
+        let response = post_signed(&app, &sample_event(event, recipient)).await;
+        assert_eq!(response.status().as_u16(), 204);
+    }
+    store_confirmed_subscriber(&app, "jane@example.com").await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "jane@example.com");
+}
+
+#[tokio::test]
+async fn production_refuses_to_start_without_a_webhook_signing_secret() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.environment = Environment::Production;
+
+    // Act
+    let with_secret = Application::build(configuration.clone()).await;
+    configuration.email_client.webhook_signing_secret = None;
+    let without_secret = Application::build(configuration).await;
+
+    // Assert
+    assert!(with_secret.is_ok());
+    assert!(without_secret.is_err());
+}
@@ -1,15 +1,20 @@
 use argon2::PasswordHasher;
+use fake::faker::{internet::en::SafeEmail, name::en::Name};
+use fake::Fake;
 use argon2::{password_hash::SaltString, Argon2};
 use zero2prod::email_client::EmailClient;
 use std::sync::LazyLock;
 use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 
 use reqwest::Url;
+use totp_rs::TOTP;
 use sqlx::{postgres::PgPoolOptions, Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    authentication::build_totp,
+    configuration::{get_configuration, DatabaseSettings, Settings},
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -32,6 +37,7 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub configuration: Settings,
 }
 
 pub struct ConfirmationLinks {
@@ -43,28 +49,36 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
+    pub role: String,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        let username = Uuid::new_v4().to_string();
         Self {
             user_id: Uuid::new_v4(),
-            username: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", username),
+            username,
             password: Uuid::new_v4().to_string(),
+            role: "owner".to_string(),
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, email, email_verified, role) \
+            VALUES ($1, $2, $3, $4, true, $5)",
             self.user_id,
             self.username,
             password_hash,
+            self.email,
+            self.role,
         )
         .execute(pool)
         .await
@@ -83,13 +97,39 @@ impl TestApp {
             .expect("Failed to post to /subscriptions")
     }
 
-    pub async fn post_newsletters<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+    pub async fn post_email_events(
+        &self,
+        body: &str,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = self
+            .api_client
+            .post(format!("{}/webhooks/email-events", &self.address))
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(timestamp) = timestamp {
+            request = request.header("Signature-Timestamp", timestamp);
+        }
+        if let Some(signature) = signature {
+            request = request.header("Signature", signature);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn get_home_html(&self) -> String {
         self.api_client
-            .post(format!("{}/admin/newsletters", &self.address))
-            .form(body)
+            .get(&self.address)
             .send()
             .await
-            .expect("Failed to execute request to post newsletter")
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_newsletters<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+        self.post_form("/admin/newsletters", body).await
     }
 
     pub async fn get_login_html(&self) -> String {
@@ -103,6 +143,23 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn get_login_oidc(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/login/oidc", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_login_oidc_callback(&self, code: &str, state: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/login/oidc/callback", &self.address))
+            .query(&[("code", code), ("state", state)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
             .get(format!("{}/admin/dashboard", &self.address))
@@ -126,15 +183,82 @@ impl TestApp {
             .unwrap()
     }
 
-    pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
         self.api_client
-            .post(format!("{}/login", &self.address))
-            .form(body)
+            .get(format!("{}/admin/subscribers/export?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscriber_data(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/data", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_data_requests_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/data-requests", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_erase_subscriber_data(&self, email: &str) -> reqwest::Response {
+        self.post_form("/admin/subscribers/erase", &[("email", email)]).await
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_subscribers_html(&self, query: &str) -> String {
+        self.get_admin_subscribers(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_audit_log_html(&self, query: &str) -> String {
+        self.get_audit_log(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_subscriber_details(&self, id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/{}", &self.address, id))
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_subscriber_details_html(&self, id: Uuid) -> String {
+        self.get_subscriber_details(id).await.text().await.unwrap()
+    }
+
+    pub async fn post_subscriber_action(&self, id: Uuid, action: &str) -> reqwest::Response {
+        self.post_form(&format!("/admin/subscribers/{}/{}", id, action), &()).await
+    }
+
+    pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+        self.post_form("/login", body).await
+    }
+
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
             .get(format!("{}/admin/password", &self.address))
@@ -151,20 +275,299 @@ impl TestApp {
         &self,
         body: &Body,
     ) -> reqwest::Response {
+        self.post_form("/admin/password", body).await
+    }
+
+    pub async fn get_login_two_factor_html(&self) -> String {
         self.api_client
-            .post(format!("{}/admin/password", &self.address))
+            .get(format!("{}/login/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.post_form("/login/2fa", &serde_json::json!({ "code": code })).await
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/2fa", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_two_factor_enrol(&self) -> reqwest::Response {
+        self.post_form("/admin/2fa/enrol", &()).await
+    }
+
+    pub async fn post_two_factor_confirm(&self, code: &str) -> reqwest::Response {
+        self.post_form("/admin/2fa/confirm", &serde_json::json!({ "code": code })).await
+    }
+
+    pub async fn post_two_factor_disable(&self, current_password: &str) -> reqwest::Response {
+        self.post_form(
+            "/admin/2fa/disable",
+            &serde_json::json!({ "current_password": current_password }),
+        )
+        .await
+    }
+
+    pub async fn get_forgot_password_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login/forgot", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_forgot_password(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_reset_password(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/login/reset", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reset_password<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/reset", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
-    pub async fn post_logout(&self) -> reqwest::Response {
+    pub async fn get_sessions_html(&self) -> String {
         self.api_client
-            .post(format!("{}/admin/logout", &self.address))
+            .get(format!("{}/admin/sessions", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, id: &str) -> reqwest::Response {
+        self.post_form(&format!("/admin/sessions/{}/revoke", id), &()).await
+    }
+
+    pub async fn post_revoke_all_sessions(&self) -> reqwest::Response {
+        self.post_form("/admin/sessions/revoke_all", &()).await
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_api_token(&self, name: &str, scopes: &[&str]) -> reqwest::Response {
+        let mut form = vec![("name", name)];
+        form.extend(scopes.iter().map(|scope| ("scope", *scope)));
+        self.post_form("/admin/api_tokens", &form).await
+    }
+
+    /// Create an API token, returning the token itself.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let html_page = self
+            .post_create_api_token("Test token", scopes)
+            .await
+            .text()
+            .await
+            .unwrap();
+        let start = html_page
+            .find(r#"<code id="api-token">"#)
+            .expect("No API token in the page")
+            + r#"<code id="api-token">"#.len();
+        let end = start + html_page[start..].find('<').unwrap();
+        html_page[start..end].to_string()
+    }
+
+    pub async fn get_api_newsletters(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/api/v1/newsletters", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_newsletters(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+        idempotency_key: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = self
+            .api_client
+            .post(format!("{}/api/v1/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(body);
+        if let Some(key) = idempotency_key {
+            request = request.header("Idempotency-Key", key);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn get_account_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/email", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_account_email(&self, email: &str) -> reqwest::Response {
+        self.post_form("/admin/email", &serde_json::json!({ "email": email })).await
+    }
+
+    /// Wait for the email server to receive `count` requests, as some are sent in the background.
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<wiremock::Request> {
+        for _ in 0..50 {
+            let requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= count {
+                return requests;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("The email server did not receive {} requests", count);
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_invite_user(&self, email: &str, role: &str) -> reqwest::Response {
+        self.post_form(
+            "/admin/users/invite",
+            &serde_json::json!({ "email": email, "role": role }),
+        )
+        .await
+    }
+
+    pub async fn post_user_role(&self, id: Uuid, role: &str) -> reqwest::Response {
+        self.post_form(
+            &format!("/admin/users/{}/role", id),
+            &serde_json::json!({ "role": role }),
+        )
+        .await
+    }
+
+    pub async fn post_user_action(&self, id: Uuid, action: &str) -> reqwest::Response {
+        self.post_form(&format!("/admin/users/{}/{}", id, action), &()).await
+    }
+
+    pub async fn get_accept_invitation(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/invitations/accept", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_accept_invitation<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.post_form("/admin/logout", &()).await
+    }
+
+    /// The CSRF token of the session of `client`, as embedded in forms.
+    pub async fn get_csrf_token(&self, client: &reqwest::Client) -> String {
+        let html = client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap();
+        let start = html
+            .find(r#"name="csrf_token" value=""#)
+            .expect("No CSRF token in the login form")
+            + r#"name="csrf_token" value=""#.len();
+        let end = start + html[start..].find('"').unwrap();
+        html[start..end].to_string()
+    }
+
+    /// Submit a form the way a browser would, with the CSRF token of the session.
+    pub async fn post_form_with<Body: serde::Serialize + ?Sized>(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+        body: &Body,
+    ) -> reqwest::Response {
+        let mut form = serde_urlencoded::to_string(body).unwrap();
+        if !form.is_empty() {
+            form.push('&');
+        }
+        let csrf_token = self.get_csrf_token(client).await;
+        form.push_str(&serde_urlencoded::to_string([("csrf_token", csrf_token)]).unwrap());
+        client
+            .post(format!("{}{}", &self.address, path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(form)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_form<Body: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.post_form_with(&self.api_client, path, body).await
     }
 
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
@@ -207,6 +610,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after adjusting its test configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     LazyLock::force(&TRACING);
 
     // Launch a mock server to stand in for mailersend's API
@@ -220,6 +628,11 @@ pub async fn spawn_app() -> TestApp {
     configuration.application.http_listen_port = 0;
     // Use the mock server as email API
     configuration.email_client.base_url = email_server.uri();
+    // Keep Redis counters of different tests apart
+    configuration.redis_key_prefix = Uuid::new_v4().to_string();
+    // Tests submit forms without rendering them first
+    configuration.subscriptions.min_form_fill_seconds = 0;
+    customise(&mut configuration);
 
     // Create and migrate the database
     configure_database(&configuration.database).await;
@@ -230,7 +643,7 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build the application");
     let port = application.port();
     let address = format!("http://127.0.0.1:{}", port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -245,7 +658,8 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client,
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client.clone().client(),
+        configuration,
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -285,6 +699,82 @@ async fn configure_database(settings: &DatabaseSettings) -> PgPool {
     connection_pool
 }
 
+pub async fn login(app: &TestApp) {
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// A code for the step after the current one, so that it has not been used yet.
+pub fn next_code(totp: &TOTP) -> String {
+    totp.generate(chrono::Utc::now().timestamp() as u64 + 30)
+}
+
+/// Enable two-factor authentication for the test user, who must be logged in.
+pub async fn enable_two_factor(app: &TestApp) -> (TOTP, Vec<String>) {
+    let response = app.post_two_factor_enrol().await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    let secret = html_page
+        .split(r#"<code id="totp-secret">"#)
+        .nth(1)
+        .and_then(|rest| rest.split('<').next())
+        .expect("The enrolment page does not show the secret");
+    let totp = build_totp(secret, &app.test_user.username).unwrap();
+
+    let response = app.post_two_factor_confirm(&totp.generate_current().unwrap()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|rest| rest.split('<').next().unwrap().to_string())
+        .collect();
+    (totp, recovery_codes)
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email,
+    })).unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -0,0 +1,236 @@
+use std::{num::NonZeroU32, time::Duration};
+
+use zero2prod::idempotency::{clean_up_idempotency_records, idempotency_table_size, request_fingerprint};
+
+use crate::helpers::{login, spawn_app, spawn_app_with, TestApp};
+
+fn newsletter_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+    })
+}
+
+/// Publish through the API with a fresh key, and return the key.
+async fn publish_with_key(app: &TestApp, token: &str) -> String {
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_api_newsletters(token, &newsletter_body("Newsletter title"), Some(&idempotency_key))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    idempotency_key
+}
+
+async fn age_key(app: &TestApp, idempotency_key: &str, hours: i64) {
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - make_interval(hours => $2) WHERE idempotency_key = $1",
+        idempotency_key,
+        hours as i32,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Mark `idempotency_key` as being processed for `body`, `seconds_ago`, as a concurrent
+/// request would have.
+async fn store_key_in_progress(app: &TestApp, idempotency_key: &str, body: &serde_json::Value, seconds_ago: i32) {
+    let request_hash = request_fingerprint(
+        "POST",
+        "/api/v1/newsletters",
+        "application/json",
+        &serde_json::to_vec(body).unwrap(),
+    );
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, api_token_id, idempotency_key, request_hash, state, created_at)
+        SELECT user_id, token_id, $1, $2, 'in_progress', now() - make_interval(secs => $3)
+        FROM api_tokens
+        "#,
+        idempotency_key,
+        request_hash,
+        seconds_ago as f64,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn count_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn duplicates_of_a_request_in_progress_are_turned_away_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let body = newsletter_body("Newsletter title");
+    store_key_in_progress(&app, &idempotency_key, &body, 0).await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &body, Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let retry_after = &app.configuration.idempotency.retry_after_seconds;
+    assert_eq!(response.headers()["Retry-After"], retry_after.to_string().as_str());
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["error"].is_string());
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn duplicates_can_wait_for_the_outcome_of_the_request_in_progress() {
+    // Arrange
+    let app = spawn_app_with(|c| c.idempotency.max_duplicate_wait_milliseconds = 10_000).await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let body = newsletter_body("Newsletter title");
+    store_key_in_progress(&app, &idempotency_key, &body, 0).await;
+    let complete_first_request = async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        sqlx::query!(
+            r#"
+            UPDATE idempotency
+            SET
+                state = 'completed',
+                response_status_code = 202,
+                response_headers = '{}',
+                response_body = '{"newsletter_issue_id":"saved"}'
+            WHERE idempotency_key = $1
+            "#,
+            idempotency_key,
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    };
+
+    // Act
+    let (response, ()) = tokio::join!(
+        app.post_api_newsletters(&token, &body, Some(&idempotency_key)),
+        complete_first_request,
+    );
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(response.text().await.unwrap(), r#"{"newsletter_issue_id":"saved"}"#);
+    assert_eq!(count_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn abandoned_keys_can_be_claimed_again() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let body = newsletter_body("Newsletter title");
+    let timeout = app.configuration.idempotency.in_progress_timeout_seconds as i32;
+    store_key_in_progress(&app, &idempotency_key, &body, timeout + 1).await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &body, Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(count_issues(&app).await, 1);
+    let state = sqlx::query!("SELECT state FROM idempotency WHERE idempotency_key = $1", idempotency_key)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .state;
+    assert_eq!(state, "completed");
+}
+
+#[tokio::test]
+async fn expired_keys_can_be_used_again() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let idempotency_key = publish_with_key(&app, &token).await;
+    age_key(&app, &idempotency_key, app.configuration.idempotency.ttl_hours + 1).await;
+
+    // Act
+    let response = app
+        .post_api_newsletters(&token, &newsletter_body("Another title"), Some(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues ORDER BY published_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[1].title, "Another title");
+}
+
+#[tokio::test]
+async fn cleaning_up_deletes_expired_keys_in_batches() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let mut settings = app.configuration.idempotency.clone();
+    settings.cleanup_batch_size = NonZeroU32::new(2).unwrap();
+    for _ in 0..5 {
+        let idempotency_key = publish_with_key(&app, &token).await;
+        age_key(&app, &idempotency_key, settings.ttl_hours + 1).await;
+    }
+    let fresh_key = publish_with_key(&app, &token).await;
+
+    // Act
+    clean_up_idempotency_records(&app.db_pool, &settings).await.unwrap();
+
+    // Assert
+    let remaining = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].idempotency_key, fresh_key);
+    let size = idempotency_table_size(&app.db_pool).await.unwrap();
+    assert_eq!(size.rows, 1);
+    assert!(size.bytes > 0);
+}
+
+#[tokio::test]
+async fn cleaning_up_leaves_keys_in_progress_until_they_are_abandoned() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    app.create_api_token(&["newsletters:publish"]).await;
+    let mut settings = app.configuration.idempotency.clone();
+    // The request may outlive the TTL, it still holds its key
+    settings.in_progress_timeout_seconds = (settings.ttl_hours + 2) * 3600;
+    let past_ttl = (settings.ttl_hours * 3600 + 60) as i32;
+    let body = newsletter_body("Newsletter title");
+    store_key_in_progress(&app, "still-running", &body, past_ttl).await;
+    store_key_in_progress(&app, "abandoned", &body, settings.in_progress_timeout_seconds as i32 + 60).await;
+
+    // Act
+    clean_up_idempotency_records(&app.db_pool, &settings).await.unwrap();
+
+    // Assert
+    let remaining = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].idempotency_key, "still-running");
+}
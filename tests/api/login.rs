@@ -1,4 +1,18 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use std::time::{Duration, Instant};
+
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Log in with `password` and return the message flashed on the login page.
+async fn failed_login_message(app: &TestApp, username: &str, password: &str) -> String {
+    let response = app.post_login(&serde_json::json!({
+        "username": username,
+        "password": password
+    })).await;
+    assert_is_redirect_to(&response, "/login");
+    app.get_login_html().await
+}
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -49,3 +63,200 @@ async fn redirect_to_admin_dashboard_after_successful_login() {
     // Assert - part 2
     assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn repeated_failures_lock_out_the_username() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.username_lockout_threshold = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+    }).await;
+    let username = app.test_user.username.clone();
+
+    // Act - part 1 - Fail until locked out
+    for _ in 0..2 {
+        let html_page = failed_login_message(&app, &username, "wrong-password").await;
+        assert!(html_page.contains("<p><i>Invalid credentials</i></p>"));
+    }
+    let html_page = failed_login_message(&app, &username, "wrong-password").await;
+
+    // Assert - part 1
+    assert!(html_page.contains("<p><i>Too many failed login attempts, please try again in 15 minutes</i></p>"));
+
+    // Act - part 2 - The right password does not help while locked out
+    let html_page = failed_login_message(&app, &username, &app.test_user.password).await;
+
+    // Assert - part 2
+    assert!(html_page.contains("Too many failed login attempts"));
+}
+
+#[tokio::test]
+async fn unknown_usernames_are_throttled_exactly_like_known_ones() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.username_lockout_threshold = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+    }).await;
+    let known_username = app.test_user.username.clone();
+    let unknown_username = uuid::Uuid::new_v4().to_string();
+
+    for _ in 0..4 {
+        // Act
+        let known = failed_login_message(&app, &known_username, "wrong-password").await;
+        let unknown = failed_login_message(&app, &unknown_username, "wrong-password").await;
+
+        // Assert
+        assert_eq!(known, unknown);
+    }
+}
+
+#[tokio::test]
+async fn the_lockout_ends_after_its_duration() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.username_lockout_threshold = 1;
+        c.login_throttling.lockout_seconds = 1;
+    }).await;
+    let html_page = failed_login_message(&app, &app.test_user.username, "wrong-password").await;
+    assert!(html_page.contains("Too many failed login attempts"));
+
+    // Act
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<p><i>The temporary lockout has ended</i></p>"));
+}
+
+#[tokio::test]
+async fn repeated_failures_from_one_address_lock_it_out() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.ip_lockout_threshold = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+    }).await;
+    for _ in 0..3 {
+        let username = uuid::Uuid::new_v4().to_string();
+        failed_login_message(&app, &username, "wrong-password").await;
+    }
+
+    // Act
+    let html_page = failed_login_message(&app, &app.test_user.username, &app.test_user.password).await;
+
+    // Assert
+    assert!(html_page.contains("Too many failed login attempts"));
+}
+
+#[tokio::test]
+async fn responses_are_delayed_after_repeated_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.free_attempts = 1;
+        c.login_throttling.base_delay_milliseconds = 500;
+    }).await;
+    failed_login_message(&app, &app.test_user.username, "wrong-password").await;
+
+    // Act
+    let start = Instant::now();
+    failed_login_message(&app, &app.test_user.username, "wrong-password").await;
+
+    // Assert
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+/// Wait for the background rehash to replace `old_hash`.
+async fn wait_for_rehash(app: &TestApp, old_hash: &str) -> String {
+    for _ in 0..50 {
+        let hash = stored_password_hash(app).await;
+        if hash != old_hash {
+            return hash;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The password hash has not been upgraded");
+}
+
+#[tokio::test]
+async fn hashes_with_outdated_parameters_are_upgraded_on_login() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 15000).await;
+    let old_hash = stored_password_hash(&app).await;
+    assert!(old_hash.contains("m=19456"));
+
+    // Act
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let new_hash = wait_for_rehash(&app, &old_hash).await;
+    assert!(new_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+    app.post_logout().await;
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn hashes_with_an_outdated_algorithm_are_upgraded_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let old_hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::default())
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        old_hash,
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let new_hash = wait_for_rehash(&app, &old_hash).await;
+    assert!(new_hash.starts_with("$argon2id$"));
+}
+
+#[tokio::test]
+async fn failed_logins_do_not_upgrade_hashes() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 15000).await;
+    let old_hash = stored_password_hash(&app).await;
+
+    // Act
+    failed_login_message(&app, &app.test_user.username, "wrong-password").await;
+
+    // Assert
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(stored_password_hash(&app).await, old_hash);
+}
@@ -6,3 +6,19 @@ mod newsletter;
 mod login;
 mod admin_dashboard;
 mod change_password;
+mod subscribers_export;
+mod subscriber_data;
+mod admin_subscribers;
+mod two_factor;
+mod admin_users;
+mod roles;
+mod admin_bootstrap;
+mod password_reset;
+mod csrf;
+mod sessions;
+mod session_timeouts;
+mod api_tokens;
+mod oidc;
+mod audit;
+mod idempotency;
+mod email_events;
@@ -1,12 +1,13 @@
-use fake::Fake;
 use std::time::Duration;
 
-use fake::faker::{internet::en::SafeEmail, name::en::Name};
 use wiremock::{
     matchers::{any, method, path}, Mock, MockBuilder, ResponseTemplate
 };
 
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, login,
+    spawn_app, spawn_app_with,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -203,8 +204,8 @@ async fn newsletter_creation_is_idempotent() {
 
 #[tokio::test]
 async fn concurrent_form_submission_is_handled_gracefully() {
-    // Arrange
-    let app = spawn_app().await;
+    // Arrange - the duplicate waits for the outcome of the first submission
+    let app = spawn_app_with(|c| c.idempotency.max_duplicate_wait_milliseconds = 10_000).await;
     create_confirmed_subscriber(&app).await;
 
     // Act part 1 - login
@@ -240,46 +241,105 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
+#[tokio::test]
+async fn newsletter_issues_record_who_published_them() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": &idempotency_key
+    })).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let published_by = sqlx::query!("SELECT published_by FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .published_by;
+    assert_eq!(published_by, Some(app.test_user.user_id));
+    let idempotency_user_id = sqlx::query!(
+        "SELECT user_id FROM idempotency WHERE idempotency_key = $1",
+        idempotency_key,
+    )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id;
+    assert_eq!(idempotency_user_id, app.test_user.user_id);
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(&app.test_user.username));
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(serde_json::json!({
-        "name": name,
-        "email": email,
-    })).unwrap();
+#[tokio::test]
+async fn resubmitting_a_form_with_different_content_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": &idempotency_key
+    })).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
 
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
+    // Act
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Another title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": &idempotency_key
+    })).await;
 
-    app.post_subscriptions(body)
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    assert_eq!(response.text().await.unwrap(), "Idempotency key reused with different payload");
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].title, "Newsletter title");
+}
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+#[tokio::test]
+async fn submitting_the_form_without_an_idempotency_key_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+    })).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.text().await.unwrap(), "Missing idempotency key");
+    let issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
         .await
-        .unwrap()
-        .pop()
         .unwrap();
-    app.get_confirmation_links(email_request)
+    assert_eq!(issues.count, 0);
+    app.dispatch_all_pending_emails().await;
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_links.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
+fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
 }
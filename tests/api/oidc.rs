@@ -0,0 +1,454 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    rand::SystemRandom,
+    rsa::PublicKeyComponents,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::{authentication::Role, configuration::OidcSettings};
+
+use crate::helpers::{assert_is_redirect_to, enable_two_factor, login, next_code, spawn_app_with, TestApp};
+
+const CLIENT_ID: &str = "zero2prod";
+
+/// A local identity provider, signing ID tokens with a test key.
+struct MockIdp {
+    server: MockServer,
+    key_pair: RsaKeyPair,
+}
+
+impl MockIdp {
+    async fn start() -> Self {
+        let server = MockServer::start().await;
+        let pem = include_str!("fixtures/oidc_signing_key.pem");
+        let der: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        let der = base64::engine::general_purpose::STANDARD.decode(der).unwrap();
+        let key_pair = RsaKeyPair::from_pkcs8(&der).unwrap();
+
+        let issuer = server.uri();
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{issuer}/authorize"),
+                "token_endpoint": format!("{issuer}/token"),
+                "jwks_uri": format!("{issuer}/jwks"),
+            })))
+            .mount(&server)
+            .await;
+        let public_key = PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "keys": [{
+                    "kty": "RSA",
+                    "kid": "test-key",
+                    "alg": "RS256",
+                    "use": "sig",
+                    "n": URL_SAFE_NO_PAD.encode(&public_key.n),
+                    "e": URL_SAFE_NO_PAD.encode(&public_key.e),
+                }]
+            })))
+            .mount(&server)
+            .await;
+        Self { server, key_pair }
+    }
+
+    fn settings(&self, auto_provision: bool) -> OidcSettings {
+        OidcSettings {
+            issuer_url: self.server.uri(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "secret".to_string(),
+            auto_provision,
+            default_role: Role::Viewer,
+        }
+    }
+
+    fn sign(&self, claims: &serde_json::Value) -> String {
+        let header = serde_json::json!({"alg": "RS256", "typ": "JWT", "kid": "test-key"});
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string()),
+        );
+        let mut signature = vec![0; self.key_pair.public().modulus_len()];
+        self.key_pair
+            .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), signed.as_bytes(), &mut signature)
+            .unwrap();
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Claims for `sub`, valid for the login started with `nonce`.
+    fn claims(&self, sub: &str, email: &str, email_verified: bool, nonce: &str) -> serde_json::Value {
+        serde_json::json!({
+            "iss": self.server.uri(),
+            "sub": sub,
+            "aud": CLIENT_ID,
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "iat": chrono::Utc::now().timestamp(),
+            "nonce": nonce,
+            "email": email,
+            "email_verified": email_verified,
+        })
+    }
+
+    async fn issue_id_token(&self, id_token: String) {
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "access-token",
+                "token_type": "Bearer",
+                "id_token": id_token,
+            })))
+            .mount(&self.server)
+            .await;
+    }
+}
+
+/// Start a login, returning the parameters of the authorization request.
+async fn start_login(app: &TestApp) -> HashMap<String, String> {
+    let response = app.get_login_oidc().await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    let url = reqwest::Url::parse(location).unwrap();
+    url.query_pairs().into_owned().collect()
+}
+
+async fn spawn_app_with_idp(auto_provision: bool) -> (TestApp, MockIdp) {
+    let idp = MockIdp::start().await;
+    let settings = idp.settings(auto_provision);
+    let app = spawn_app_with(|c| c.oidc = Some(settings)).await;
+    (app, idp)
+}
+
+#[tokio::test]
+async fn the_login_form_offers_single_sign_on_only_when_configured() {
+    // Arrange
+    let (app, _idp) = spawn_app_with_idp(false).await;
+    let app_without_sso = spawn_app_with(|_| {}).await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+    let html_page_without_sso = app_without_sso.get_login_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<a href="/login/oidc">"#));
+    assert!(!html_page_without_sso.contains(r#"<a href="/login/oidc">"#));
+    let response = app_without_sso.get_login_oidc().await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_authorization_request_uses_pkce() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+
+    // Act
+    let params = start_login(&app).await;
+
+    // Assert
+    assert_eq!(params["response_type"], "code");
+    assert_eq!(params["client_id"], CLIENT_ID);
+    assert_eq!(params["redirect_uri"], format!("{}/login/oidc/callback", app.configuration.application.base_url));
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert!(params["scope"].split(' ').any(|s| s == "openid"));
+
+    // The token request must prove it comes from whoever started the login
+    let id_token = idp.sign(&idp.claims("subject", "someone@example.com", true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+    app.get_login_oidc_callback("code", &params["state"]).await;
+    let token_request = idp
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/token")
+        .unwrap();
+    let form: HashMap<String, String> = serde_urlencoded::from_bytes(&token_request.body).unwrap();
+    assert_eq!(form["code"], "code");
+    assert_eq!(
+        URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes())),
+        params["code_challenge"]
+    );
+}
+
+#[tokio::test]
+async fn a_verified_email_logs_in_its_user_and_links_the_subject() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email.to_uppercase(), true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+    let subject = sqlx::query!("SELECT oidc_subject FROM users WHERE user_id = $1", app.test_user.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .oidc_subject;
+    assert_eq!(subject.as_deref(), Some("subject"));
+}
+
+#[tokio::test]
+async fn users_with_two_factor_authentication_still_need_a_code() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    login(&app).await;
+    let (totp, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act - part 1 - Single sign-on alone is not enough
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert - part 1
+    assert_is_redirect_to(&response, "/login/2fa");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - part 2 - The code completes the login
+    let response = app.post_login_two_factor(&next_code(&totp)).await;
+
+    // Assert - part 2
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_email_the_user_set_for_themselves_is_not_linked() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    login(&app).await;
+    app.post_account_email("victim@example.com").await;
+    app.post_logout().await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("victim-subject", "victim@example.com", true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let subject = sqlx::query!("SELECT oidc_subject FROM users WHERE user_id = $1", app.test_user.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .oidc_subject;
+    assert_eq!(subject, None);
+}
+
+#[tokio::test]
+async fn a_linked_subject_logs_in_whatever_its_email() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    sqlx::query!("UPDATE users SET oidc_subject = 'subject' WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", "new-address@example.com", false, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_unverified_email_is_not_trusted() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(true).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, false, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("There is no account for this identity"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn unknown_users_are_provisioned_when_enabled() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(true).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("new-subject", "newcomer@example.com", true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let user = sqlx::query!(
+        "SELECT username, email, role FROM users WHERE oidc_subject = 'new-subject'",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(user.username, "newcomer@example.com");
+    assert_eq!(user.email.as_deref(), Some("newcomer@example.com"));
+    assert_eq!(user.role, "viewer");
+}
+
+#[tokio::test]
+async fn users_are_not_provisioned_from_an_invalid_email_claim() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(true).await;
+    let params = start_login(&app).await;
+    let claims = idp.claims("new-subject", "<script>alert(1)</script>@example.com", true, &params["nonce"]);
+    idp.issue_id_token(idp.sign(&claims)).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let users = sqlx::query!("SELECT count(*) AS \"count!\" FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, 1);
+}
+
+#[tokio::test]
+async fn unknown_users_are_rejected_when_provisioning_is_disabled() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("new-subject", "newcomer@example.com", true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let users = sqlx::query!("SELECT count(*) AS \"count!\" FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, 1);
+}
+
+#[tokio::test]
+async fn deactivated_users_cannot_log_in() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    sqlx::query!("UPDATE users SET active = false WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your account has been deactivated"));
+}
+
+#[tokio::test]
+async fn callbacks_with_the_wrong_state_are_rejected() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, true, &params["nonce"]));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", "forged-state").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    // The login cannot be completed afterwards either
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+    assert_is_redirect_to(&response, "/login");
+    let token_requests = idp
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.path() == "/token")
+        .count();
+    assert_eq!(token_requests, 0);
+}
+
+#[tokio::test]
+async fn id_tokens_with_the_wrong_nonce_are_rejected() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, true, "another-nonce"));
+    idp.issue_id_token(id_token).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn id_tokens_with_an_invalid_signature_are_rejected() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let id_token = idp.sign(&idp.claims("subject", &app.test_user.email, true, &params["nonce"]));
+    // Swap in claims for another subject, keeping the original signature
+    let mut parts: Vec<String> = id_token.split('.').map(String::from).collect();
+    let forged = idp.claims("someone-else", &app.test_user.email, true, &params["nonce"]);
+    parts[1] = URL_SAFE_NO_PAD.encode(forged.to_string());
+    idp.issue_id_token(parts.join(".")).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Single sign-on failed, please try again"));
+}
+
+#[tokio::test]
+async fn expired_id_tokens_are_rejected() {
+    // Arrange
+    let (app, idp) = spawn_app_with_idp(false).await;
+    let params = start_login(&app).await;
+    let mut claims = idp.claims("subject", &app.test_user.email, true, &params["nonce"]);
+    claims["exp"] = serde_json::json!(chrono::Utc::now().timestamp() - 3600);
+    idp.issue_id_token(idp.sign(&claims)).await;
+
+    // Act
+    let response = app.get_login_oidc_callback("code", &params["state"]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,237 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, spawn_app_with, TestApp};
+
+const REQUESTED_MESSAGE: &str =
+    "If an account uses this email address, we have sent it a link to reset its password";
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// Request a reset for the test user and return the token from the email.
+async fn request_reset_token(app: &TestApp) -> String {
+    let response = app.post_forgot_password(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/login/forgot");
+    let email_request = app.wait_for_emails(1).await.pop().unwrap();
+    let reset_link = app.get_confirmation_links(&email_request).html;
+    assert_eq!(reset_link.path(), "/login/reset");
+    reset_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .expect("The reset link has no token")
+}
+
+#[tokio::test]
+async fn the_response_is_the_same_whether_the_email_is_known_or_not() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+
+    // Act - part 1 - An unknown email
+    let response = app.post_forgot_password("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/login/forgot");
+    let unknown_html = app.get_forgot_password_html().await;
+
+    // Act - part 2 - The email of the test user, in a different case
+    let response = app.post_forgot_password(&app.test_user.email.to_uppercase()).await;
+    assert_is_redirect_to(&response, "/login/forgot");
+    let known_html = app.get_forgot_password_html().await;
+
+    // Assert
+    assert!(unknown_html.contains(REQUESTED_MESSAGE));
+    assert_eq!(unknown_html, known_html);
+    let requests = app.wait_for_emails(1).await;
+    assert_eq!(requests.len(), 1);
+}
+
+#[tokio::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = request_reset_token(&app).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act - part 1 - Follow the link
+    let response = app.get_reset_password(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(&token));
+
+    // Act - part 2 - Choose a new password
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your password has been reset, you can now log in"));
+
+    // Act - part 3 - Log in with the new password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_token_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = request_reset_token(&app).await;
+    let body = serde_json::json!({
+        "token": &token,
+        "new_password": "a-first-new-password",
+        "new_password_check": "a-first-new-password",
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    let response = app.post_reset_password(&body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot");
+    let html_page = app.get_forgot_password_html().await;
+    assert!(html_page.contains("This password reset link is invalid or has expired"));
+    let response = app.get_reset_password(&token).await;
+    assert_is_redirect_to(&response, "/login/forgot");
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_reset.token_ttl_minutes = 0).await;
+    mount_email_server(&app).await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let response = app.get_reset_password(&token).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot");
+    let html_page = app.get_forgot_password_html().await;
+    assert!(html_page.contains("This password reset link is invalid or has expired"));
+}
+
+#[tokio::test]
+async fn new_passwords_must_match() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": "a-new-password",
+            "new_password_check": "another-new-password",
+        }))
+        .await;
+
+    // Assert
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("/login/reset?token="));
+    // The token has not been used up
+    let response = app.get_reset_password(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("You entered two different new passwords"));
+}
+
+#[tokio::test]
+async fn weak_new_passwords_are_rejected_without_using_up_the_token() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": "short",
+            "new_password_check": "short",
+        }))
+        .await;
+
+    // Assert
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("/login/reset?token="));
+    let response = app.get_reset_password(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("The password must be at least 12 characters long"));
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": &token,
+            "new_password": "a-first-new-password",
+            "new_password_check": "a-first-new-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_every_session() {
+    // Arrange
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    login(&app).await;
+    let token = request_reset_token(&app).await;
+
+    // Act - Reset the password from another browser
+    let other_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = other_client
+        .post(format!("{}/login/reset", &app.address))
+        .form(&serde_json::json!({
+            "token": &token,
+            "new_password": "a-new-password",
+            "new_password_check": "a-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has ended, please log in again"));
+}
+
+#[tokio::test]
+async fn users_can_set_the_email_reset_links_are_sent_to() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_account_email("new-address@example.com").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = app.get_account_email_html().await;
+    assert!(html_page.contains("Your email address has been updated"));
+    assert!(html_page.contains(r#"value="new-address@example.com""#));
+}
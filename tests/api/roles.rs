@@ -0,0 +1,152 @@
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp, TestUser};
+
+/// Store a user with `role` and log in as them.
+async fn login_with_role(app: &TestApp, role: &str) -> TestUser {
+    let mut user = TestUser::generate();
+    user.role = role.to_string();
+    user.store(&app.db_pool).await;
+    let response = app.post_login(&serde_json::json!({
+        "username": &user.username,
+        "password": &user.password
+    })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    user
+}
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })
+}
+
+#[tokio::test]
+async fn viewers_can_browse_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    login_with_role(&app, "viewer").await;
+
+    // Act
+    let response = app.get_admin_subscribers("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.get_admin_dashboard_html().await.contains("Your role is viewer."));
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    login_with_role(&app, "viewer").await;
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<p><i>You need the editor role to do this</i></p>"));
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn viewers_cannot_change_subscriptions() {
+    // Arrange
+    let app = spawn_app().await;
+    login_with_role(&app, "viewer").await;
+
+    // Act
+    let response = app.post_subscriber_action(uuid::Uuid::new_v4(), "delete").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    login_with_role(&app, "editor").await;
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for role in ["viewer", "editor"] {
+        login_with_role(&app, role).await;
+
+        // Act
+        let response = app.post_invite_user("new-user@example.com", "owner").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403, "{} managed users", role);
+        let html_page = app.get_admin_dashboard_html().await;
+        assert!(html_page.contains("<p><i>You need the owner role to do this</i></p>"));
+    }
+}
+
+#[tokio::test]
+async fn role_changes_apply_to_existing_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = login_with_role(&app, "editor").await;
+    let response = app.post_newsletters(&newsletter_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - The owner demotes the editor from another browser
+    let owner_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = app
+        .post_form_with(&owner_client, "/login", &serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app
+        .post_form_with(
+            &owner_client,
+            &format!("/admin/users/{}/role", editor.user_id),
+            &serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert
+    let response = app.post_newsletters(&newsletter_body()).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owners_cannot_change_their_own_role() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_user_role(app.test_user.user_id, "viewer").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    assert!(app.get_admin_users_html().await.contains("You cannot change your own role"));
+    let response = app.post_newsletters(&newsletter_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use redis::AsyncCommands;
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app_with, TestApp};
+
+/// How long the session of the test user stays registered, in seconds.
+async fn registered_session_ttl(app: &TestApp) -> i64 {
+    let client = redis::Client::open(app.configuration.redis_uri.as_str()).unwrap();
+    let mut connection = client.get_multiplexed_async_connection().await.unwrap();
+    let prefix = &app.configuration.redis_key_prefix;
+    let session_ids: Vec<String> = connection
+        .smembers(format!("{}:user_sessions:{}", prefix, app.test_user.user_id))
+        .await
+        .unwrap();
+    assert_eq!(session_ids.len(), 1);
+    connection
+        .ttl(format!("{}:session:{}", prefix, session_ids[0]))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn idle_sessions_are_logged_out() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sessions.idle_timeout_seconds = 1).await;
+    login(&app).await;
+
+    // Act
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("You have been logged out after a period of inactivity"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn activity_keeps_sessions_alive() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sessions.idle_timeout_seconds = 2).await;
+    login(&app).await;
+
+    for _ in 0..3 {
+        // Act
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let response = app.get_admin_dashboard().await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_logged_out_after_the_absolute_timeout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sessions.absolute_timeout_seconds = 2).await;
+    login(&app).await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has expired, please log in again"));
+}
+
+#[tokio::test]
+async fn timed_out_sessions_can_log_in_again() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sessions.idle_timeout_seconds = 1).await;
+    login(&app).await;
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    login(&app).await;
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sessions_stay_registered_for_as_long_as_the_timeouts_allow() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.sessions.idle_timeout_seconds = 2 * 24 * 3600;
+        c.sessions.absolute_timeout_seconds = 3 * 24 * 3600;
+    })
+    .await;
+
+    // Act
+    login(&app).await;
+
+    // Assert
+    let ttl = registered_session_ttl(&app).await;
+    assert!(ttl > 2 * 24 * 3600 - 60 && ttl <= 2 * 24 * 3600, "Registered for {}s", ttl);
+}
+
+#[tokio::test]
+async fn sessions_are_not_registered_past_the_absolute_timeout() {
+    // Arrange
+    let app = spawn_app_with(|c| c.sessions.absolute_timeout_seconds = 60).await;
+
+    // Act
+    login(&app).await;
+    app.get_admin_dashboard().await;
+
+    // Assert
+    let ttl = registered_session_ttl(&app).await;
+    assert!(ttl > 0 && ttl <= 60, "Registered for {}s", ttl);
+}
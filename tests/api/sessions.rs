@@ -0,0 +1,163 @@
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp, TestUser};
+
+/// Log `user` in from another browser, returning its client.
+async fn login_elsewhere(app: &TestApp, user: &TestUser, user_agent: &str) -> reqwest::Client {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent(user_agent)
+        .build()
+        .unwrap();
+    let response = app
+        .post_form_with(&client, "/login", &serde_json::json!({
+            "username": &user.username,
+            "password": &user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+async fn get_dashboard_with(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// The ids of the sessions that can be revoked from the sessions page.
+fn revocable_session_ids(html_page: &str) -> Vec<String> {
+    html_page
+        .split(r#"action="/admin/sessions/"#)
+        .skip(1)
+        .filter_map(|s| s.split_once("/revoke\""))
+        .map(|(id, _)| id.to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn sessions_page_lists_the_sessions_of_the_user() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    login_elsewhere(&app, &app.test_user, "Other browser").await;
+
+    // Act
+    let html_page = app.get_sessions_html().await;
+
+    // Assert
+    assert!(html_page.contains("This session"));
+    assert!(html_page.contains("Other browser"));
+    assert!(html_page.contains("127.0.0.1"));
+    assert_eq!(revocable_session_ids(&html_page).len(), 1);
+}
+
+#[tokio::test]
+async fn sessions_of_other_users_are_not_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    login_elsewhere(&app, &other_user, "Other browser").await;
+    login(&app).await;
+
+    // Act
+    let html_page = app.get_sessions_html().await;
+
+    // Assert
+    assert!(!html_page.contains("Other browser"));
+}
+
+#[tokio::test]
+async fn revoking_a_session_logs_it_out() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_client = login_elsewhere(&app, &app.test_user, "Other browser").await;
+    let ids = revocable_session_ids(&app.get_sessions_html().await);
+
+    // Act
+    let response = app.post_revoke_session(&ids[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let response = get_dashboard_with(&app, &other_client).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("The session has been logged out"));
+    assert!(!html_page.contains("Other browser"));
+}
+
+#[tokio::test]
+async fn sessions_of_other_users_cannot_be_revoked() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    let other_client = login_elsewhere(&app, &other_user, "Other browser").await;
+    login_elsewhere(&app, &other_user, "Third browser").await;
+    let html_page = other_client
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let ids = revocable_session_ids(&html_page);
+    login(&app).await;
+
+    // Act
+    let response = app.post_revoke_session(&ids[0]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let response = get_dashboard_with(&app, &other_client).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn revoking_all_sessions_logs_out_everywhere() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_client = login_elsewhere(&app, &app.test_user, "Other browser").await;
+
+    // Act
+    let response = app.post_revoke_all_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("You have been logged out everywhere"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = get_dashboard_with(&app, &other_client).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_client = login_elsewhere(&app, &app.test_user, "Other browser").await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let response = get_dashboard_with(&app, &other_client).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
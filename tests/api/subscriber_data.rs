@@ -0,0 +1,143 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, login, spawn_app, TestApp};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriber email")
+        .email
+}
+
+async fn publish_and_deliver_newsletter(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_handle_data_requests() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let export = app.get_subscriber_data("ursula@example.com").await;
+    let erase = app.post_erase_subscriber_data("ursula@example.com").await;
+
+    // Assert
+    assert_is_redirect_to(&export, "/login");
+    assert_is_redirect_to(&erase, "/login");
+}
+
+#[tokio::test]
+async fn data_export_contains_subscriptions_tokens_and_delivery_history() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+
+    // Act
+    let response = app.get_subscriber_data(&email).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let bundle: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(bundle["email"], email.as_str());
+    assert_eq!(bundle["subscriptions"].as_array().unwrap().len(), 1);
+    assert_eq!(bundle["subscriptions"][0]["status"], "confirmed");
+    assert_eq!(bundle["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(bundle["queued_deliveries"].as_array().unwrap().len(), 0);
+    assert_eq!(bundle["delivery_history"].as_array().unwrap().len(), 1);
+    assert_eq!(bundle["delivery_history"][0]["title"], "Newsletter title");
+    assert_eq!(bundle["delivery_history"][0]["outcome"], "delivered");
+}
+
+#[tokio::test]
+async fn data_export_for_an_unknown_email_is_empty() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscriber_data("nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let bundle: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(bundle["subscriptions"], serde_json::json!([]));
+    assert_eq!(bundle["delivery_history"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn erasure_deletes_every_row_about_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+    // Leave a delivery in the queue as well
+    let response = app.post_newsletters(&serde_json::json!({
+        "title": "Another title",
+        "content_text": "Text content",
+        "content_html": "<p>Html content</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    let response = app.post_erase_subscriber_data(&email).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/data-requests");
+    let html_page = app.get_data_requests_html().await;
+    assert!(html_page.contains("All data about the subscriber has been erased (4 records)"));
+
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT count(*) FROM subscription_tokens) AS "subscription_tokens!",
+            (SELECT count(*) FROM issue_delivery_queue) AS "queued_deliveries!",
+            (SELECT count(*) FROM issue_delivery_history) AS "delivery_history!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.subscription_tokens, 0);
+    assert_eq!(remaining.queued_deliveries, 0);
+    assert_eq!(remaining.delivery_history, 0);
+}
+
+#[tokio::test]
+async fn erasing_an_unknown_email_is_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_erase_subscriber_data("nobody@example.com").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/data-requests");
+    let html_page = app.get_data_requests_html().await;
+    assert!(html_page.contains("We do not hold any data about this subscriber"));
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, login, spawn_app, TestApp};
+
+async fn insert_subscriber(app: &TestApp, email: &str, status: &str, subscribed_at: &str) {
+    let subscribed_at: DateTime<Utc> = subscribed_at.parse().unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'Le Guin', $3, $4)
+        "#,
+        Uuid::new_v4(),
+        email,
+        subscribed_at,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert subscriber");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn csv_export_contains_a_header_and_one_line_per_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ursula@example.com", "confirmed", "2024-01-01T00:00:00Z").await;
+    insert_subscriber(&app, "le.guin@example.com", "pending_confirmation", "2024-02-01T00:00:00Z").await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "text/csv; charset=utf-8");
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "id,email,name,subscribed_at,status");
+    assert!(lines[1].contains("ursula@example.com"));
+    assert!(lines[1].ends_with(",confirmed"));
+    assert!(lines[2].contains("le.guin@example.com"));
+}
+
+#[tokio::test]
+async fn csv_export_escapes_cells_that_a_spreadsheet_would_evaluate() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=%3DHYPERLINK&email=ursula%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert!(lines[1].contains(",ursula@example.com,'=HYPERLINK,"));
+}
+
+#[tokio::test]
+async fn json_export_is_an_array_of_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ursula@example.com", "confirmed", "2024-01-01T00:00:00Z").await;
+    insert_subscriber(&app, "le.guin@example.com", "pending_confirmation", "2024-02-01T00:00:00Z").await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=json").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let subscribers = body.as_array().unwrap();
+    assert_eq!(subscribers.len(), 2);
+    assert_eq!(subscribers[0]["email"], "ursula@example.com");
+    assert_eq!(subscribers[0]["status"], "confirmed");
+    assert_eq!(subscribers[1]["email"], "le.guin@example.com");
+}
+
+#[tokio::test]
+async fn json_export_of_an_empty_list_is_an_empty_array() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=json").await;
+
+    // Assert
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn export_can_be_filtered_by_status_and_subscription_date() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "january@example.com", "confirmed", "2024-01-01T00:00:00Z").await;
+    insert_subscriber(&app, "february@example.com", "confirmed", "2024-02-01T00:00:00Z").await;
+    insert_subscriber(&app, "pending@example.com", "pending_confirmation", "2024-02-02T00:00:00Z").await;
+    insert_subscriber(&app, "march@example.com", "confirmed", "2024-03-01T00:00:00Z").await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .get_subscribers_export(
+            "format=json&status=confirmed\
+            &subscribed_after=2024-01-15T00:00:00Z\
+            &subscribed_before=2024-03-01T00:00:00Z",
+        )
+        .await;
+
+    // Assert
+    let body: serde_json::Value = response.json().await.unwrap();
+    let emails: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(emails, vec!["february@example.com"]);
+}
+
+#[tokio::test]
+async fn export_rejects_an_unknown_format() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=xml").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -1,6 +1,6 @@
 use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_200_for_valid_form_data() {
@@ -116,3 +116,251 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     // The two links should be the same
     assert_eq!(confirmation_links.html, confirmation_links.text);
 }
+
+#[tokio::test]
+async fn subscribe_normalises_the_email_address() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=Le%20Guin&email=%20Ursula_Le_Guin%40GMail.com%20";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula_Le_Guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribing_twice_with_a_differently_cased_address_keeps_one_subscription() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The second attempt re-sends the confirmation email
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let first = test_app
+        .post_subscriptions("name=Le%20Guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+    let second = test_app
+        .post_subscriptions("name=Le%20Guin&email=ursula_le_guin%40GMAIL.COM".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula_Le_Guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_does_not_send_another_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=Le%20Guin&email=ursula_le_guin%40gmail.com";
+
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    test_app.post_subscriptions(body.into()).await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=Le%20Guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_and_role_addresses() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=Le%20Guin&email=ursula%40mailinator.com",
+            "Addresses from disposable email providers are not accepted",
+        ),
+        (
+            "name=Le%20Guin&email=noreply%40gmail.com",
+            "Role addresses such as noreply@ are not accepted",
+        ),
+    ];
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    for (body, expected_message) in test_cases {
+        // Act
+        let response = test_app.post_subscriptions(body.into()).await;
+
+        // Assert
+        assert_eq!(400, response.status().as_u16());
+        assert!(response.text().await.unwrap().contains(expected_message));
+    }
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_once_a_client_exceeds_its_rate_limit() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.subscriptions.ip_rate_limit.max_requests = 2).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    for i in 0..2 {
+        let body = format!("name=Le%20Guin&email=ursula_le_guin_{}%40gmail.com", i);
+        let response = test_app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=Le%20Guin&email=ursula_le_guin_2%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_once_an_address_exceeds_its_rate_limit() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.subscriptions.email_rate_limit.max_requests = 1).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions("name=Le%20Guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act - the limit applies to the address, however it is spelled
+    let response = test_app
+        .post_subscriptions("name=Le%20Guin&email=Ursula_Le_Guin%40GMAIL.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn subscribe_silently_ignores_submissions_filling_in_the_honeypot() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=Le%20Guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&test_app.db_pool)
+        .await
+        .expect("Failed to query subscriptions.");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_forms_submitted_too_quickly() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.subscriptions.min_form_fill_seconds = 1).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let html_page = test_app.get_home_html().await;
+    let form_token = html_page
+        .split(r#"name="form_token" value=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("The home page does not contain a form token");
+    let body = format!(
+        "name=Le%20Guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        form_token
+    );
+
+    // Act - part 1 - without a token
+    let response = test_app
+        .post_subscriptions("name=Le%20Guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    // Act - part 2 - right after rendering the form
+    let response = test_app.post_subscriptions(body.clone()).await;
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("too quickly"));
+
+    // Act - part 3 - after the minimum fill time
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let response = test_app.post_subscriptions(body).await;
+    assert_eq!(200, response.status().as_u16());
+}
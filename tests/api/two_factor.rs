@@ -0,0 +1,192 @@
+use crate::helpers::{
+    assert_is_redirect_to, enable_two_factor, login, next_code, spawn_app, spawn_app_with, TestApp,
+};
+
+async fn post_password_login(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_two_factor_enrol().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn enrolment_shows_a_provisioning_uri_and_recovery_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    assert!(app.get_two_factor_html().await.contains("Two-factor authentication is disabled"));
+
+    // Act - part 1 - Start the enrolment
+    app.post_two_factor_enrol().await;
+    let html_page = app.get_two_factor_html().await;
+
+    // Assert - part 1
+    assert!(html_page.contains("otpauth://totp/"));
+    assert!(html_page.contains("<svg"));
+
+    // Act - part 2 - A wrong code does not enable anything
+    let response = app.post_two_factor_confirm("000000").await;
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The code is not valid, please try again</i></p>"));
+
+    // Act - part 3 - Confirm with the right code
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+
+    // Assert - part 3
+    assert_eq!(recovery_codes.len(), 10);
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled. You have 10 recovery codes left."));
+}
+
+#[tokio::test]
+async fn login_asks_for_a_code_when_two_factor_authentication_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (totp, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - part 1 - The password alone is not enough
+    let response = post_password_login(&app).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - part 2 - A wrong code is rejected
+    let response = app.post_login_two_factor("000000").await;
+    assert_is_redirect_to(&response, "/login/2fa");
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Invalid authentication code</i></p>"));
+
+    // Act - part 3 - The right code logs in
+    let code = next_code(&totp);
+    let response = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
+
+    // Act - part 4 - The same code cannot be used twice
+    app.post_logout().await;
+    post_password_login(&app).await;
+    let response = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - part 1
+    post_password_login(&app).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert - part 1
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You have used a recovery code, 9 are left"));
+
+    // Act - part 2
+    app.post_logout().await;
+    post_password_login(&app).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert - part 2
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[tokio::test]
+async fn wrong_codes_count_as_failed_logins() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.username_lockout_threshold = 2;
+        c.login_throttling.base_delay_milliseconds = 0;
+    }).await;
+    login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    post_password_login(&app).await;
+
+    // Act
+    app.post_login_two_factor("000000").await;
+    let response = app.post_login_two_factor("000000").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many failed login attempts"));
+    let response = app.post_login_two_factor("000000").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_password_does_not_reset_the_count_of_wrong_codes() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.username_lockout_threshold = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+    }).await;
+    login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act
+    for _ in 0..2 {
+        let response = post_password_login(&app).await;
+        assert_is_redirect_to(&response, "/login/2fa");
+        app.post_login_two_factor("000000").await;
+    }
+    post_password_login(&app).await;
+    let response = app.post_login_two_factor("000000").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many failed login attempts"));
+    let response = post_password_login(&app).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_authentication_requires_the_current_password() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    enable_two_factor(&app).await;
+
+    // Act - part 1 - Wrong password
+    let response = app.post_two_factor_disable("wrong-password").await;
+
+    // Assert - part 1
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is enabled"));
+
+    // Act - part 2 - Right password
+    let response = app.post_two_factor_disable(&app.test_user.password).await;
+
+    // Assert - part 2
+    assert_is_redirect_to(&response, "/admin/2fa");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled</i></p>"));
+    app.post_logout().await;
+    let response = post_password_login(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}